@@ -0,0 +1,105 @@
+use mlua::prelude::*;
+
+// the options table of add_clip, in seconds
+#[derive(Default)]
+pub struct ClipOptions{
+    pub offset: f32,
+    pub length: Option<f32>,
+    pub fade_in: f32,
+    pub fade_out: f32,
+}
+
+impl ClipOptions{
+    const KEYS: [&'static str; 4] = ["offset", "length", "fade_in", "fade_out"];
+}
+
+impl<'lua> FromLua<'lua> for ClipOptions{
+    fn from_lua(value: LuaValue<'lua>, _: &'lua Lua) -> LuaResult<Self>{
+        match value{
+            LuaValue::Nil => Ok(Self::default()),
+            LuaValue::Table(table) => {
+                for key in table.clone().pairs::<String, LuaValue>(){
+                    let (key, _) = key?;
+                    if !Self::KEYS.contains(&key.as_str()){
+                        return Err(LuaError::RuntimeError(format!(
+                            "add_clip: unknown option \"{}\", options are: {}",
+                            key, Self::KEYS.join(", ")
+                        )));
+                    }
+                }
+                Ok(Self{
+                    offset: table.get::<_, Option<f32>>("offset")?.unwrap_or(0.0),
+                    length: table.get("length")?,
+                    fade_in: table.get::<_, Option<f32>>("fade_in")?.unwrap_or(0.0),
+                    fade_out: table.get::<_, Option<f32>>("fade_out")?.unwrap_or(0.0),
+                })
+            },
+            other => Err(LuaError::FromLuaConversionError{
+                from: other.type_name(),
+                to: "ClipOptions",
+                message: Some(String::from("expected a table of options")),
+            }),
+        }
+    }
+}
+
+// Where a clip sits on the timeline and what part of the sample it plays, in frames.
+pub struct ClipConf{
+    pub start: usize,
+    pub offset: usize,
+    pub length: usize,
+    pub fade_in: usize,
+    pub fade_out: usize,
+}
+
+impl ClipConf{
+    pub fn new(start_sec: f32, options: &ClipOptions, sample_len: usize, sr: usize) -> Self{
+        let frames = |sec: f32| (sec.max(0.0) * sr as f32) as usize;
+        let offset = frames(options.offset).min(sample_len);
+        let rest = sample_len - offset;
+        Self{
+            start: frames(start_sec),
+            offset,
+            length: options.length.map_or(rest, |l| frames(l).min(rest)),
+            fade_in: frames(options.fade_in),
+            fade_out: frames(options.fade_out),
+        }
+    }
+
+    // frame of the sample at a frame of the timeline, None outside of the clip
+    #[inline]
+    pub fn position(&self, time: usize) -> Option<usize>{
+        let pos = time.checked_sub(self.start)?;
+        if pos < self.length { Some(pos) } else { None }
+    }
+
+    // linear fades at both ends, pos counts from the start of the clip
+    #[inline]
+    pub fn amp(&self, pos: usize) -> f32{
+        let fin = if pos < self.fade_in { pos as f32 / self.fade_in as f32 } else { 1.0 };
+        let left = self.length - pos;
+        let fout = if left < self.fade_out { left as f32 / self.fade_out as f32 } else { 1.0 };
+        fin * fout
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::clip::*;
+
+    #[test]
+    fn placement_and_fades(){
+        let options = ClipOptions{ offset: 1.0, length: Some(2.0), fade_in: 0.5, fade_out: 1.0 };
+        let clip = ClipConf::new(10.0, &options, 100, 10);
+        assert_eq!((clip.start, clip.offset, clip.length), (100, 10, 20));
+        assert_eq!(clip.position(99), None);
+        assert_eq!(clip.position(100), Some(0));
+        assert_eq!(clip.position(120), None);
+        assert_eq!(clip.amp(0), 0.0);
+        assert_eq!(clip.amp(10), 1.0);
+        assert_eq!(clip.amp(15), 0.5);
+        // the length is cut off at the end of the sample
+        let long = ClipOptions{ offset: 9.0, length: Some(5.0), ..Default::default() };
+        assert_eq!(ClipConf::new(0.0, &long, 100, 10).length, 10);
+    }
+}
@@ -52,6 +52,7 @@ pub struct Settings{
     buffer_length: Option<usize>,
     project_samplerate: Option<usize>,
     workflow: Option<String>,
+    sample_cache: Option<String>,
 }
 
 impl Settings{
@@ -63,6 +64,15 @@ impl Settings{
         self.project_samplerate.unwrap_or(44100)
     }
 
+    // directory of the sample cache relative to the project, None when turned off
+    pub fn sample_cache(&self) -> Option<String>{
+        match &self.sample_cache{
+            Some(dir) if dir.is_empty() => None,
+            Some(dir) => Some(dir.clone()),
+            None => Some(String::from(".termdaw-cache")),
+        }
+    }
+
     pub fn workflow(&self) -> WorkFlow{
         if let Some(string) = &self.workflow{
             match string.as_ref() {
@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use realfft::{ RealFftPlanner, RealToComplex, ComplexToReal, num_complex::Complex };
+
+use crate::adsr::lerp;
+
+// Uniformly partitioned overlap-save convolution.
+// The impulse response is cut into partitions of one block each, every partition is transformed
+// once. Every block the spectrum of the input is pushed into a frequency domain delay line and
+// multiplied with the partitions, so the cost per block grows linearly with the ir length
+// instead of with the ir length times the block length.
+pub struct Convolver{
+    block: usize,
+    fft: Arc<dyn RealToComplex<f32>>,
+    ifft: Arc<dyn ComplexToReal<f32>>,
+    parts: Vec<Vec<Complex<f32>>>,
+    fdl: Vec<Vec<Complex<f32>>>,
+    fdl_pos: usize,
+    input: Vec<f32>,
+    time: Vec<f32>,
+    acc: Vec<Complex<f32>>,
+}
+
+impl Convolver{
+    pub fn new(ir: &[f32], block: usize, planner: &mut RealFftPlanner<f32>) -> Self{
+        let block = block.max(1);
+        let n = block * 2;
+        let fft = planner.plan_fft_forward(n);
+        let ifft = planner.plan_fft_inverse(n);
+        let bins = n / 2 + 1;
+        let partitions = ir.len().div_ceil(block).max(1);
+        let mut parts = Vec::with_capacity(partitions);
+        let mut time = vec![0.0; n];
+        for p in 0..partitions{
+            time.iter_mut().for_each(|s| *s = 0.0);
+            let start = (p * block).min(ir.len());
+            let end = (start + block).min(ir.len());
+            time[..end - start].copy_from_slice(&ir[start..end]);
+            let mut spectrum = vec![Complex::new(0.0, 0.0); bins];
+            fft.process(&mut time, &mut spectrum).expect("Convolver: fft size mismatch");
+            parts.push(spectrum);
+        }
+        Self{
+            block,
+            fft,
+            ifft,
+            parts,
+            fdl: vec![vec![Complex::new(0.0, 0.0); bins]; partitions],
+            fdl_pos: 0,
+            input: vec![0.0; n],
+            time,
+            acc: vec![Complex::new(0.0, 0.0); bins],
+        }
+    }
+
+    pub fn block(&self) -> usize{
+        self.block
+    }
+
+    pub fn clear(&mut self){
+        self.input.iter_mut().for_each(|s| *s = 0.0);
+        for spectrum in self.fdl.iter_mut(){
+            spectrum.iter_mut().for_each(|c| *c = Complex::new(0.0, 0.0));
+        }
+        self.fdl_pos = 0;
+    }
+
+    // convolves exactly one block in place, mixed with the dry input by wet
+    pub fn process(&mut self, io: &mut [f32], wet: f32){
+        let b = self.block;
+        let partitions = self.parts.len();
+        self.input.copy_within(b.., 0);
+        self.input[b..].copy_from_slice(&io[..b]);
+        self.time.copy_from_slice(&self.input);
+        self.fft.process(&mut self.time, &mut self.fdl[self.fdl_pos])
+            .expect("Convolver: fft size mismatch");
+        self.acc.iter_mut().for_each(|c| *c = Complex::new(0.0, 0.0));
+        for (p, part) in self.parts.iter().enumerate(){
+            let x = &self.fdl[(self.fdl_pos + partitions - p) % partitions];
+            for ((a, x), h) in self.acc.iter_mut().zip(x).zip(part){
+                *a += x * h;
+            }
+        }
+        // the result is real, get rid of rounding errors the inverse fft would complain about
+        let last = self.acc.len() - 1;
+        self.acc[0].im = 0.0;
+        self.acc[last].im = 0.0;
+        self.ifft.process(&mut self.acc, &mut self.time).expect("Convolver: fft size mismatch");
+        let scale = 1.0 / (b * 2) as f32;
+        // the input of this block is still in the second half
+        for ((o, s), dry) in io.iter_mut().zip(&self.time[b..]).zip(&self.input[b..]){
+            *o = lerp(*dry, s * scale, wet);
+        }
+        self.fdl_pos = (self.fdl_pos + 1) % partitions;
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::convolution::*;
+
+    #[test]
+    fn convolution_matches_direct(){
+        let ir = (0..37).map(|i| ((i * 7 % 11) as f32 - 5.0) / 5.0).collect::<Vec<_>>();
+        let input = (0..64).map(|i| ((i * 3 % 13) as f32 - 6.0) / 6.0).collect::<Vec<_>>();
+        let mut direct = vec![0.0; input.len()];
+        for (n, d) in direct.iter_mut().enumerate(){
+            for (k, h) in ir.iter().enumerate(){
+                if k > n { break; }
+                *d += h * input[n - k];
+            }
+        }
+        let mut planner = RealFftPlanner::new();
+        let mut conv = Convolver::new(&ir, 8, &mut planner);
+        let mut out = input.clone();
+        for chunk in out.chunks_mut(8){
+            conv.process(chunk, 1.0);
+        }
+        for (a, b) in out.iter().zip(&direct){
+            assert!((a - b).abs() < 0.001);
+        }
+        // fully dry leaves the input alone
+        let mut dry = input.clone();
+        for chunk in dry.chunks_mut(8){
+            conv.process(chunk, 0.0);
+        }
+        assert_eq!(dry, input);
+    }
+}
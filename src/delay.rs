@@ -0,0 +1,147 @@
+// Ring buffer that can be read at fractional delays
+pub struct DelayLine{
+    buf: Vec<f32>,
+    pos: usize,
+}
+
+impl DelayLine{
+    pub fn new(max_delay_frames: usize) -> Self{
+        Self{
+            buf: vec![0.0; max_delay_frames + 2],
+            pos: 0,
+        }
+    }
+
+    pub fn clear(&mut self){
+        self.buf.iter_mut().for_each(|s| *s = 0.0);
+        self.pos = 0;
+    }
+
+    #[inline]
+    pub fn write(&mut self, x: f32){
+        self.buf[self.pos] = x;
+        self.pos = (self.pos + 1) % self.buf.len();
+    }
+
+    // read the value written delay frames ago, delay is clamped to [1, max_delay_frames]
+    #[inline]
+    pub fn read(&self, delay: f32) -> f32{
+        let len = self.buf.len();
+        let delay = delay.clamp(1.0, (len - 2) as f32);
+        let whole = delay.floor();
+        let frac = delay - whole;
+        let a = (self.pos + len - whole as usize) % len;
+        let b = (a + len - 1) % len;
+        self.buf[a] + frac * (self.buf[b] - self.buf[a])
+    }
+}
+
+// Parse a delay time to seconds. Either milliseconds ("375" or "375ms") or a note division
+// relative to a quarter note at the given bpm ("1/8", dotted "1/8d" or triplet "1/8t").
+pub fn parse_delay_time(string: &str, bpm: f32) -> Option<f32>{
+    let string = string.trim();
+    if let Some((num, den)) = string.split_once('/'){
+        let (den, mul) = if let Some(den) = den.strip_suffix('d'){ (den, 1.5) }
+            else if let Some(den) = den.strip_suffix('t'){ (den, 2.0 / 3.0) }
+            else { (den, 1.0) };
+        let num = num.trim().parse::<f32>().ok()?;
+        let den = den.trim().parse::<f32>().ok()?;
+        if den <= 0.0 || bpm <= 0.0 { return None; }
+        let whole_note_sec = 4.0 * 60.0 / bpm;
+        Some(whole_note_sec * num / den * mul)
+    } else {
+        let ms = string.strip_suffix("ms").unwrap_or(string).trim().parse::<f32>().ok()?;
+        if ms < 0.0 { return None; }
+        Some(ms * 0.001)
+    }
+}
+
+pub struct StereoDelay{
+    lines: [DelayLine; 2],
+    times: [f32; 2],
+    feedback: f32,
+    ping_pong: bool,
+    gamma: f32,
+    damped: [f32; 2],
+}
+
+impl StereoDelay{
+    pub fn new(
+        time_l_sec: f32, time_r_sec: f32, feedback: f32, ping_pong: bool, damping_hz: f32,
+        sr: usize
+    ) -> Self{
+        let frames = |sec: f32| (sec * sr as f32).max(1.0);
+        let times = [frames(time_l_sec), frames(time_r_sec)];
+        let line = |frames: f32| DelayLine::new(frames.ceil() as usize + 1);
+        // one pole lowpass in the feedback path, zero or less means no damping
+        let gamma = if damping_hz <= 0.0 { 1.0 }
+        else {
+            1.0 - std::f32::consts::E.powf(
+                -2.0 * std::f32::consts::PI * damping_hz.min(sr as f32 * 0.49) / sr as f32
+            )
+        };
+        Self{
+            lines: [line(times[0]), line(times[1])],
+            times,
+            feedback: feedback.clamp(0.0, 0.99),
+            ping_pong,
+            gamma,
+            damped: [0.0; 2],
+        }
+    }
+
+    pub fn clear(&mut self){
+        self.lines.iter_mut().for_each(|line| line.clear());
+        self.damped = [0.0; 2];
+    }
+
+    // returns only the echoes
+    #[inline]
+    pub fn process(&mut self, l: f32, r: f32) -> (f32, f32){
+        let dl = self.lines[0].read(self.times[0]);
+        let dr = self.lines[1].read(self.times[1]);
+        self.damped[0] += self.gamma * (dl - self.damped[0]);
+        self.damped[1] += self.gamma * (dr - self.damped[1]);
+        let [fl, fr] = self.damped;
+        if self.ping_pong{
+            // mono input enters on the left and bounces between the channels
+            self.lines[0].write((l + r) * 0.5 + fr * self.feedback);
+            self.lines[1].write(fl * self.feedback);
+        } else {
+            self.lines[0].write(l + fl * self.feedback);
+            self.lines[1].write(r + fr * self.feedback);
+        }
+        (dl, dr)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::delay::*;
+
+    #[test]
+    fn delay_time_parsing(){
+        let eq = |a: Option<f32>, b: f32| (a.unwrap() - b).abs() < 0.0001;
+        assert!(eq(parse_delay_time("250", 120.0), 0.25));
+        assert!(eq(parse_delay_time("250ms", 120.0), 0.25));
+        assert!(eq(parse_delay_time("1/4", 120.0), 0.5));
+        assert!(eq(parse_delay_time("1/8", 120.0), 0.25));
+        assert!(eq(parse_delay_time("1/8d", 120.0), 0.375));
+        assert!(eq(parse_delay_time("1/4t", 120.0), 1.0 / 3.0));
+        assert!(eq(parse_delay_time("3/16", 60.0), 0.75));
+        assert!(parse_delay_time("1/0", 120.0).is_none());
+        assert!(parse_delay_time("-5", 120.0).is_none());
+        assert!(parse_delay_time("fast", 120.0).is_none());
+    }
+
+    #[test]
+    fn delay_line_read(){
+        let mut line = DelayLine::new(8);
+        for i in 0..5{
+            line.write(i as f32);
+        }
+        assert!((line.read(1.0) - 4.0).abs() < 0.0001);
+        assert!((line.read(3.0) - 2.0).abs() < 0.0001);
+        assert!((line.read(1.5) - 3.5).abs() < 0.0001);
+    }
+}
@@ -0,0 +1,280 @@
+use std::f32::consts::PI;
+
+use crate::adsr::lerp;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DistortionMode{ Soft, Hard, Tanh, Fold, Tube, Crush }
+
+impl DistortionMode{
+    pub fn from(string: &str) -> Self{
+        match string{
+            "hard" | "hard-clip" => DistortionMode::Hard,
+            "tanh" => DistortionMode::Tanh,
+            "fold" | "foldback" => DistortionMode::Fold,
+            "tube" => DistortionMode::Tube,
+            "crush" | "bitcrush" => DistortionMode::Crush,
+            _ => DistortionMode::Soft,
+        }
+    }
+}
+
+#[inline]
+pub fn shape(mode: DistortionMode, x: f32) -> f32{
+    match mode{
+        // cubic soft clipper, smooth up to the rails at -1 and 1
+        DistortionMode::Soft => {
+            let x = x.clamp(-1.5, 1.5);
+            x - (4.0 / 27.0) * x * x * x
+        },
+        DistortionMode::Hard => x.clamp(-1.0, 1.0),
+        DistortionMode::Tanh => x.tanh(),
+        // mirror everything above one back down
+        DistortionMode::Fold => {
+            let y = (x + 1.0).rem_euclid(4.0);
+            (if y < 2.0 { y } else { 4.0 - y }) - 1.0
+        },
+        // asymmetric: the positive half clips softly and early, the negative half late
+        DistortionMode::Tube => {
+            if x >= 0.0 { 1.0 - (-x).exp() }
+            else { (x * 0.5).tanh() * 2.0 }
+        },
+        DistortionMode::Crush => x,
+    }
+}
+
+// taps of the oversampling filters per unit of the factor
+const TAPS: usize = 16;
+
+// Linear phase windowed sinc lowpass, used to band limit before decimating and after stuffing
+// zeroes when oversampling. The history is kept doubled so the convolution reads one slice.
+struct Fir{
+    taps: Vec<f32>,
+    hist: Vec<f32>,
+    pos: usize,
+}
+
+impl Fir{
+    fn lowpass(factor: usize) -> Self{
+        let len = TAPS * factor + 1;
+        let cutoff = 0.45 / factor as f32;
+        let mid = (len / 2) as f32;
+        let mut taps = (0..len).map(|i| {
+            let x = i as f32 - mid;
+            let sinc = if x == 0.0 { 2.0 * cutoff }
+            else { (2.0 * PI * cutoff * x).sin() / (PI * x) };
+            let window = 0.42 - 0.5 * (2.0 * PI * i as f32 / (len - 1) as f32).cos()
+                + 0.08 * (4.0 * PI * i as f32 / (len - 1) as f32).cos();
+            sinc * window
+        }).collect::<Vec<_>>();
+        let sum = taps.iter().sum::<f32>();
+        taps.iter_mut().for_each(|t| *t /= sum);
+        Self{ hist: vec![0.0; len * 2], taps, pos: 0 }
+    }
+
+    fn clear(&mut self){
+        self.hist.iter_mut().for_each(|s| *s = 0.0);
+        self.pos = 0;
+    }
+
+    #[inline]
+    fn push(&mut self, x: f32){
+        let len = self.taps.len();
+        self.pos = (self.pos + len - 1) % len;
+        self.hist[self.pos] = x;
+        self.hist[self.pos + len] = x;
+    }
+
+    #[inline]
+    fn output(&self) -> f32{
+        let len = self.taps.len();
+        self.hist[self.pos..self.pos + len].iter().zip(&self.taps).map(|(h, t)| h * t).sum()
+    }
+}
+
+struct Channel{
+    up: Fir,
+    down: Fir,
+    tone: f32,
+    hold: f32,
+    hold_count: usize,
+    // the dry signal, delayed as much as the filters delay the wet one
+    dry: Vec<f32>,
+    dry_pos: usize,
+}
+
+pub struct Distortion{
+    mode: DistortionMode,
+    drive: f32,
+    bias: f32,
+    tone_gamma: f32,
+    factor: usize,
+    levels: f32,
+    downsample: usize,
+    channels: [Channel; 2],
+}
+
+impl Distortion{
+    // drive is a linear gain into the shaper, bias an offset that makes it asymmetric, tone the
+    // cut off of a lowpass after the shaper (zero for none). oversampling of 1 means none.
+    // bits and downsample are only used by the crush mode.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        mode: DistortionMode, drive: f32, bias: f32, tone_hz: f32, oversampling: usize,
+        bits: f32, downsample: usize, sr: usize
+    ) -> Self{
+        let factor = oversampling.clamp(1, 16);
+        let osr = (sr * factor) as f32;
+        let tone_gamma = if tone_hz <= 0.0 { 1.0 }
+        else { 1.0 - (-2.0 * PI * tone_hz.min(sr as f32 * 0.49) / osr).exp() };
+        // both filters delay by half their length at the oversampled rate
+        let latency = if factor > 1 { TAPS } else { 0 };
+        let channel = || Channel{
+            up: Fir::lowpass(factor),
+            down: Fir::lowpass(factor),
+            tone: 0.0,
+            hold: 0.0,
+            hold_count: 0,
+            dry: vec![0.0; latency + 1],
+            dry_pos: 0,
+        };
+        Self{
+            mode,
+            drive: drive.max(0.0),
+            bias,
+            tone_gamma,
+            factor,
+            levels: 2.0f32.powf(bits.clamp(1.0, 32.0) - 1.0),
+            downsample: downsample.max(1),
+            channels: [channel(), channel()],
+        }
+    }
+
+    pub fn clear(&mut self){
+        for c in self.channels.iter_mut(){
+            c.up.clear();
+            c.down.clear();
+            c.tone = 0.0;
+            c.hold = 0.0;
+            c.hold_count = 0;
+            c.dry.iter_mut().for_each(|s| *s = 0.0);
+            c.dry_pos = 0;
+        }
+    }
+
+    #[inline]
+    fn shape_one(&self, x: f32) -> f32{
+        if self.mode == DistortionMode::Crush{
+            let x = (x * self.drive).clamp(-1.0, 1.0);
+            (x * self.levels).round() / self.levels
+        } else {
+            // take out the dc the bias would introduce
+            shape(self.mode, x * self.drive + self.bias) - shape(self.mode, self.bias)
+        }
+    }
+
+    // mixed with the dry input by wet, which is delayed to line up with the distorted signal
+    #[inline]
+    pub fn process(&mut self, l: f32, r: f32, wet: f32) -> (f32, f32){
+        let mut out = [0.0; 2];
+        for (c, x) in [l, r].into_iter().enumerate(){
+            let ch = &mut self.channels[c];
+            ch.dry[ch.dry_pos] = x;
+            ch.dry_pos = (ch.dry_pos + 1) % ch.dry.len();
+            let dry = ch.dry[ch.dry_pos];
+            let x = if self.mode == DistortionMode::Crush{
+                // sample rate reduction: hold a value for a number of frames
+                let ch = &mut self.channels[c];
+                if ch.hold_count == 0{
+                    ch.hold = x;
+                }
+                ch.hold_count = (ch.hold_count + 1) % self.downsample;
+                ch.hold
+            } else {
+                x
+            };
+            let mut y = 0.0;
+            for k in 0..self.factor{
+                // zero stuffing, the gain of the lost energy is made up by the factor
+                let stuffed = if k == 0 { x * self.factor as f32 } else { 0.0 };
+                let up = if self.factor > 1{
+                    self.channels[c].up.push(stuffed);
+                    self.channels[c].up.output()
+                } else {
+                    x
+                };
+                let shaped = self.shape_one(up);
+                let ch = &mut self.channels[c];
+                ch.tone += self.tone_gamma * (shaped - ch.tone);
+                if self.factor > 1{
+                    ch.down.push(ch.tone);
+                    // the phase of the input sample, so the delay is a whole number of frames
+                    if k == 0{
+                        y = ch.down.output();
+                    }
+                } else {
+                    y = ch.tone;
+                }
+            }
+            out[c] = lerp(dry, y, wet);
+        }
+        (out[0], out[1])
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::distortion::*;
+
+    #[test]
+    fn shapes_stay_bounded(){
+        let modes = [
+            DistortionMode::Soft, DistortionMode::Hard, DistortionMode::Tanh,
+            DistortionMode::Fold, DistortionMode::Tube,
+        ];
+        for mode in modes{
+            for i in -100..100{
+                let y = shape(mode, i as f32 * 0.1);
+                assert!(y.abs() <= 2.0001);
+                if mode != DistortionMode::Tube{
+                    assert!(y.abs() <= 1.0001);
+                }
+            }
+            assert!(shape(mode, 0.0).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn oversampling_passes_low_frequencies(){
+        let mut dist = Distortion::new(DistortionMode::Hard, 1.0, 0.0, 0.0, 4, 16.0, 1, 48000);
+        let mut max: f32 = 0.0;
+        for i in 0..4800{
+            let x = (i as f32 * 2.0 * PI * 100.0 / 48000.0).sin() * 0.5;
+            let (l, _) = dist.process(x, x, 1.0);
+            if i > 2400{
+                max = max.max(l.abs());
+            }
+        }
+        assert!((max - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn dry_lines_up_with_wet(){
+        // clean through the shaper, so any mix of wet and dry should keep the level, also where
+        // a dry signal 16 frames early would cancel the wet one
+        let mut dist = Distortion::new(DistortionMode::Hard, 1.0, 0.0, 0.0, 4, 16.0, 1, 44100);
+        let x = |i: usize| (i as f32 * 2.0 * PI / 32.0).sin() * 0.5;
+        let mut max: f32 = 0.0;
+        for i in 0..4410{
+            let (l, _) = dist.process(x(i), x(i), 0.5);
+            if i > 2205{
+                max = max.max(l.abs());
+            }
+        }
+        assert!((max - 0.5).abs() < 0.02, "{}", max);
+        let mut dry = Distortion::new(DistortionMode::Hard, 1.0, 0.0, 0.0, 4, 16.0, 1, 44100);
+        for i in 0..100{
+            let (l, _) = dry.process(x(i), x(i), 0.0);
+            assert_eq!(l, if i < 16 { 0.0 } else { x(i - 16) });
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{
+    filter::{ Svf, SvfCoefs, SvfMode },
+    noise::Rng,
+    synth::poly_blep,
+};
+
+use std::f32::consts::PI;
+
+// All decay times are the time it takes to fall 60db
+#[derive(Clone, Copy, Debug)]
+pub enum DrumConf{
+    // a sine that sweeps down from pitch_start_hz to pitch_end_hz, with a noise click on top
+    Kick{
+        pitch_start_hz: f32,
+        pitch_end_hz: f32,
+        pitch_decay_sec: f32,
+        decay_sec: f32,
+        click: f32,
+    },
+    // two detuned sines for the body and highpassed noise for the snares
+    Snare{
+        tone_hz: f32,
+        tone_decay_sec: f32,
+        noise_decay_sec: f32,
+        noise_hz: f32,
+        tone_mix: f32,
+    },
+    // a cluster of six square waves at inharmonic ratios mixed with noise, highpassed
+    Hat{
+        decay_sec: f32,
+        filter_hz: f32,
+        metallic: f32,
+    },
+}
+
+// the ratios of the 808 cymbal oscillators
+const HAT_HZ: [f32; 6] = [205.3, 304.4, 369.6, 522.7, 540.0, 800.0];
+// square cluster tuning so the lowest one sits around 2khz
+const HAT_TUNE: f32 = 10.0;
+
+impl DrumConf{
+    // after this long a hit is silent and can be dropped
+    pub fn length_sec(&self) -> f32{
+        match self{
+            // the click outlasts a very short body
+            Self::Kick { decay_sec, .. } => decay_sec.max(0.005),
+            Self::Snare { tone_decay_sec, noise_decay_sec, .. } =>
+                tone_decay_sec.max(*noise_decay_sec),
+            Self::Hat { decay_sec, .. } => *decay_sec,
+        }.max(0.001)
+    }
+}
+
+// exponential decay to -60db at decay_sec
+#[inline]
+fn decay(t: f32, decay_sec: f32) -> f32{
+    (-6.907755 * t / decay_sec.max(0.001)).exp()
+}
+
+pub struct DrumHit{
+    t: usize,
+    vel: f32,
+    phases: [f32; 6],
+    rng: Rng,
+    svf: Svf,
+    coefs: SvfCoefs,
+    hp: (f32, f32),
+}
+
+impl DrumHit{
+    pub fn new(conf: &DrumConf, vel: f32, seed: u64, sr: usize) -> Self{
+        let filter_hz = if let DrumConf::Hat { filter_hz, .. } = conf { *filter_hz }
+        else { 1000.0 };
+        Self{
+            t: 0,
+            vel,
+            phases: [0.0; 6],
+            rng: Rng::new(seed),
+            svf: Svf::default(),
+            coefs: SvfCoefs::new(filter_hz.clamp(20.0, sr as f32 * 0.45), 0.3, sr),
+            hp: (0.0, 0.0),
+        }
+    }
+
+    pub fn done(&self, conf: &DrumConf, sr: usize) -> bool{
+        self.t as f32 / sr as f32 > conf.length_sec()
+    }
+
+    #[inline]
+    pub fn next(&mut self, conf: &DrumConf, sr: usize) -> f32{
+        let srf = sr as f32;
+        let t = self.t as f32 / srf;
+        self.t += 1;
+        let s = match conf{
+            DrumConf::Kick { pitch_start_hz, pitch_end_hz, pitch_decay_sec, decay_sec, click } => {
+                let sweep = decay(t, *pitch_decay_sec);
+                let hz = pitch_end_hz + (pitch_start_hz - pitch_end_hz) * sweep;
+                let body = (self.phases[0] * 2.0 * PI).sin() * decay(t, *decay_sec);
+                self.phases[0] = (self.phases[0] + hz / srf).fract();
+                let click = self.rng.next_bipolar() * click * decay(t, 0.005);
+                body + click
+            },
+            DrumConf::Snare { tone_hz, tone_decay_sec, noise_decay_sec, noise_hz, tone_mix } => {
+                let mut tone = 0.0;
+                for (k, ratio) in [1.0, 1.47].iter().enumerate(){
+                    tone += (self.phases[k] * 2.0 * PI).sin() * 0.5;
+                    self.phases[k] = (self.phases[k] + tone_hz * ratio / srf).fract();
+                }
+                // one pole highpass on the noise
+                let a = (-2.0 * PI * noise_hz.clamp(1.0, srf * 0.49) / srf).exp();
+                let x = self.rng.next_bipolar();
+                let noise = a * (self.hp.1 + x - self.hp.0);
+                self.hp = (x, noise);
+                let mix = tone_mix.clamp(0.0, 1.0);
+                tone * decay(t, *tone_decay_sec) * mix
+                    + noise * decay(t, *noise_decay_sec) * (1.0 - mix)
+            },
+            DrumConf::Hat { decay_sec, metallic, .. } => {
+                let mut squares = 0.0;
+                for (phase, hz) in self.phases.iter_mut().zip(HAT_HZ.iter()){
+                    // band limited, the partials go up high enough to alias
+                    let dt = hz * HAT_TUNE / srf;
+                    let naive = if *phase < 0.5 { 1.0 } else { -1.0 };
+                    squares += naive + poly_blep(*phase, dt)
+                        - poly_blep((*phase + 0.5).fract(), dt);
+                    *phase = (*phase + dt).fract();
+                }
+                let mix = metallic.clamp(0.0, 1.0);
+                let x = squares / 6.0 * mix + self.rng.next_bipolar() * (1.0 - mix);
+                self.svf.process(x, &self.coefs, SvfMode::High) * decay(t, *decay_sec)
+            },
+        };
+        s * self.vel
+    }
+}
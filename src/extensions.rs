@@ -3,6 +3,22 @@ use crate::{
     floww::FlowwBank,
     adsr::*,
     synth::*,
+    filter::*,
+    reverb::Reverb,
+    convolution::Convolver,
+    delay::StereoDelay,
+    modulation::{ ModDelay, Phaser },
+    distortion::{ Distortion, DistortionMode },
+    noise::{ Noise, NoiseColor },
+    fm::{ FmConf, FmVoice, MAX_OPERATORS },
+    pluck::{ PluckConf, PluckVoice },
+    drums::{ DrumConf, DrumHit },
+    sampler::{ SamplerConf, GATE_RELEASE_SEC, Interpolation, read_interpolated },
+    instrument::{ Zone, LoopMode },
+    granular::Granular,
+    slice::Slices,
+    stream::StreamReader,
+    clip::ClipConf,
     graph::GenArgs,
     lv2::Lv2Host,
 };
@@ -11,6 +27,16 @@ use core::f32::consts::PI;
 use std::collections::VecDeque;
 
 use sampsyn::*;
+use realfft::RealFftPlanner;
+
+// (note, vel, env_t, rel_t, the zones it plays as (zone index, position))
+type InstrumentNote = (f32, f32, f32, f32, Vec<(usize, f64)>);
+
+// where a clip gets its audio
+pub enum ClipSource{
+    Memory(usize),
+    Stream(Box<StreamReader>),
+}
 
 pub enum VertexExt{
     Sum,
@@ -46,7 +72,7 @@ pub enum VertexExt{
         square_conf: OscConf,
         topflat_conf: OscConf,
         triangle_conf: OscConf,
-        notes: Vec<(f32, f32, f32, f32)>,
+        notes: Vec<(f32, f32, f32, f32, ())>,
     },
     SampSyn{
         floww_index: usize,
@@ -76,7 +102,115 @@ pub enum VertexExt{
         hprevr: f32,
         first: bool,
         pass: bool,
-    }
+    },
+    Filter{
+        mode: SvfMode,
+        cutoff_hz: f32,
+        resonance: f32,
+        floww_index: Option<usize>,
+        env_oct: f32,
+        key_track: f32,
+        vel_amount: f32,
+        conf: AdsrConf,
+        voice: Option<(f32, f32, f32, f32)>,
+        svfl: Svf,
+        svfr: Svf,
+    },
+    Reverb{
+        reverb: Box<Reverb>,
+    },
+    Convolver{
+        sample_index: usize,
+        ir_key: (usize, usize),
+        convolvers: Vec<Convolver>,
+    },
+    Delay{
+        delay: Box<StereoDelay>,
+    },
+    Chorus{
+        chorus: Box<ModDelay>,
+    },
+    Flanger{
+        flanger: Box<ModDelay>,
+    },
+    Phaser{
+        phaser: Box<Phaser>,
+    },
+    Distortion{
+        distortion: Box<Distortion>,
+    },
+    Noise{
+        floww_index: Option<usize>,
+        adsr: AdsrConf,
+        color: NoiseColor,
+        seed: u64,
+        noise: [Noise; 2],
+        notes: Vec<(f32, f32, f32, f32, ())>,
+    },
+    Fm{
+        floww_index: usize,
+        conf: Box<FmConf>,
+        notes: Vec<(f32, f32, f32, f32, FmVoice)>,
+    },
+    Va{
+        floww_index: usize,
+        conf: VaConf,
+        notes: Vec<(f32, f32, f32, f32, VaVoice)>,
+    },
+    Pluck{
+        floww_index: usize,
+        conf: PluckConf,
+        seed: u64,
+        t: usize,
+        notes: Vec<(f32, f32, f32, f32, PluckVoice)>,
+    },
+    Drum{
+        floww_index: usize,
+        note: Option<usize>,
+        conf: DrumConf,
+        t: usize,
+        hits: Vec<DrumHit>,
+    },
+    Sampler{
+        sample_index: usize,
+        floww_index: usize,
+        conf: SamplerConf,
+        notes: Vec<(f32, f32, f32, f32, f64)>,
+    },
+    Instrument{
+        floww_index: usize,
+        // the zones with the index of their sample
+        zones: Vec<(Zone, usize)>,
+        // round robin counters per key
+        hits: Vec<usize>,
+        notes: Vec<InstrumentNote>,
+    },
+    Granular{
+        sample_index: usize,
+        floww_index: Option<usize>,
+        position_floww_index: Option<usize>,
+        granular: Box<Granular>,
+        // held (note, vel)
+        notes: Vec<(f32, f32)>,
+    },
+    Slicer{
+        sample_index: usize,
+        floww_index: usize,
+        base_note: f32,
+        slices: Slices,
+        gains: Vec<f32>,
+        reverse: Vec<bool>,
+        // (slice, frames played, vel)
+        playing: Vec<(usize, usize, f32)>,
+    },
+    StreamLoop{
+        reader: Box<StreamReader>,
+    },
+    Clip{
+        source: ClipSource,
+        conf: ClipConf,
+        t: usize,
+    },
 }
 
 impl VertexExt{
@@ -193,12 +327,253 @@ impl VertexExt{
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
+    pub fn filter(
+        mode: SvfMode, cutoff_hz: f32, resonance: f32, floww_index: Option<usize>, env_oct: f32,
+        key_track: f32, vel_amount: f32, conf: AdsrConf
+    ) -> Self{
+        Self::Filter{
+            mode,
+            cutoff_hz,
+            resonance,
+            floww_index,
+            env_oct,
+            key_track,
+            vel_amount,
+            conf,
+            voice: None,
+            svfl: Svf::default(),
+            svfr: Svf::default(),
+        }
+    }
+
+    pub fn reverb(
+        room_size: f32, damping: f32, pre_delay_ms: f32, width: f32, sampling_hz: usize
+    ) -> Self{
+        Self::Reverb{
+            reverb: Box::new(Reverb::new(room_size, damping, pre_delay_ms, width, sampling_hz)),
+        }
+    }
+
+    pub fn convolver(sample_index: usize) -> Self{
+        Self::Convolver{
+            sample_index,
+            ir_key: (0, 0),
+            convolvers: Vec::new(),
+        }
+    }
+
+    pub fn delay(
+        time_l_sec: f32, time_r_sec: f32, feedback: f32, ping_pong: bool, damping_hz: f32,
+        sampling_hz: usize
+    ) -> Self{
+        Self::Delay{
+            delay: Box::new(StereoDelay::new(
+                time_l_sec, time_r_sec, feedback, ping_pong, damping_hz, sampling_hz
+            )),
+        }
+    }
+
+    pub fn chorus(
+        rate_hz: f32, depth_ms: f32, feedback: f32, stereo_phase_deg: f32, sampling_hz: usize
+    ) -> Self{
+        Self::Chorus{
+            chorus: Box::new(ModDelay::chorus(
+                rate_hz, depth_ms, feedback, stereo_phase_deg, sampling_hz
+            )),
+        }
+    }
+
+    pub fn flanger(
+        rate_hz: f32, depth_ms: f32, feedback: f32, stereo_phase_deg: f32, sampling_hz: usize
+    ) -> Self{
+        Self::Flanger{
+            flanger: Box::new(ModDelay::flanger(
+                rate_hz, depth_ms, feedback, stereo_phase_deg, sampling_hz
+            )),
+        }
+    }
+
+    pub fn phaser(
+        rate_hz: f32, depth: f32, feedback: f32, stereo_phase_deg: f32, stages: usize,
+        sampling_hz: usize
+    ) -> Self{
+        Self::Phaser{
+            phaser: Box::new(Phaser::new(
+                rate_hz, depth, feedback, stereo_phase_deg, stages, sampling_hz
+            )),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn distortion(
+        mode: DistortionMode, drive: f32, bias: f32, tone_hz: f32, oversampling: usize,
+        bits: f32, downsample: usize, sampling_hz: usize
+    ) -> Self{
+        Self::Distortion{
+            distortion: Box::new(Distortion::new(
+                mode, drive, bias, tone_hz, oversampling, bits, downsample, sampling_hz
+            )),
+        }
+    }
+
+    pub fn noise(color: NoiseColor, seed: u64, floww_index: Option<usize>, adsr: AdsrConf) -> Self{
+        Self::Noise{
+            floww_index,
+            adsr,
+            color,
+            seed,
+            noise: noise_pair(color, seed, 0),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn fm(floww_index: usize, conf: FmConf) -> Self{
+        Self::Fm{
+            floww_index,
+            conf: Box::new(conf),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn va(floww_index: usize, conf: VaConf) -> Self{
+        Self::Va{
+            floww_index,
+            conf,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn pluck(floww_index: usize, conf: PluckConf, seed: u64) -> Self{
+        Self::Pluck{
+            floww_index,
+            conf,
+            seed,
+            t: 0,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn drum(floww_index: usize, note: Option<usize>, conf: DrumConf) -> Self{
+        Self::Drum{
+            floww_index,
+            note,
+            conf,
+            t: 0,
+            hits: Vec::new(),
+        }
+    }
+
+    pub fn sampler(sample_index: usize, floww_index: usize, conf: SamplerConf) -> Self{
+        Self::Sampler{
+            sample_index,
+            floww_index,
+            conf,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn instrument(floww_index: usize, zones: Vec<(Zone, usize)>) -> Self{
+        Self::Instrument{
+            floww_index,
+            zones,
+            hits: vec![0; 128],
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn granular(
+        sample_index: usize, floww_index: Option<usize>, position_floww_index: Option<usize>,
+        granular: Granular
+    ) -> Self{
+        Self::Granular{
+            sample_index,
+            floww_index,
+            position_floww_index,
+            granular: Box::new(granular),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn slicer(
+        sample_index: usize, floww_index: usize, base_note: f32, slices: Slices, gains: Vec<f32>,
+        reverse: Vec<bool>
+    ) -> Self{
+        Self::Slicer{
+            sample_index,
+            floww_index,
+            base_note,
+            slices,
+            gains,
+            reverse,
+            playing: Vec::new(),
+        }
+    }
+
+    pub fn stream_loop(reader: StreamReader) -> Self{
+        Self::StreamLoop{ reader: Box::new(reader) }
+    }
+
+    pub fn clip(mut source: ClipSource, conf: ClipConf) -> Self{
+        if let ClipSource::Stream(reader) = &mut source{
+            reader.set_time(conf.offset);
+        }
+        Self::Clip{ source, conf, t: 0 }
+    }
+
     pub fn set_time(&mut self, time: usize){
         match self{
             Self::SampleLoop { t, .. } => { *t = time; },
             Self::DebugSine { notes, .. } => { notes.clear(); },
             Self::Synth { notes, .. } => { notes.clear(); },
             Self::BandPass { first, .. } => { *first = true; },
+            Self::Filter { voice, svfl, svfr, .. } => {
+                *voice = None;
+                svfl.reset();
+                svfr.reset();
+            },
+            Self::Reverb { reverb } => { reverb.clear(); },
+            Self::Convolver { convolvers, .. } => {
+                convolvers.iter_mut().for_each(|c| c.clear());
+            },
+            // the transport jumped: echoes of what played before the jump should not sound
+            Self::Delay { delay } => { delay.clear(); },
+            Self::Chorus { chorus } => { chorus.set_time(time); },
+            Self::Flanger { flanger } => { flanger.set_time(time); },
+            Self::Phaser { phaser } => { phaser.set_time(time); },
+            Self::Distortion { distortion } => { distortion.clear(); },
+            Self::Noise { color, seed, noise, notes, .. } => {
+                *noise = noise_pair(*color, *seed, time);
+                notes.clear();
+            },
+            Self::Fm { notes, .. } => { notes.clear(); },
+            Self::Va { notes, .. } => { notes.clear(); },
+            Self::Pluck { t, notes, .. } => {
+                *t = time;
+                notes.clear();
+            },
+            Self::Drum { t, hits, .. } => {
+                *t = time;
+                hits.clear();
+            },
+            Self::Sampler { notes, .. } => { notes.clear(); },
+            Self::Instrument { hits, notes, .. } => {
+                hits.iter_mut().for_each(|h| *h = 0);
+                notes.clear();
+            },
+            Self::Granular { granular, notes, .. } => {
+                granular.set_time(time);
+                notes.clear();
+            },
+            Self::Slicer { playing, .. } => { playing.clear(); },
+            Self::StreamLoop { reader } => { reader.set_time(time); },
+            Self::Clip { source, conf, t } => {
+                *t = time;
+                if let ClipSource::Stream(reader) = source{
+                    let pos = time.saturating_sub(conf.start).min(conf.length);
+                    reader.set_time(conf.offset + pos);
+                }
+            },
             _ => {  },
         }
     }
@@ -257,7 +632,84 @@ impl VertexExt{
                 band_pass_gen(
                     buf, len, wet, first, *pass, *lgamma, *hgamma, lprevl, lprevr, hprevl, hprevr
                 );
-            }
+            },
+            Self::Filter {
+                mode, cutoff_hz, resonance, floww_index, env_oct, key_track, vel_amount, conf,
+                voice, svfl, svfr
+            } => {
+                filter_gen(
+                    buf, len, fb, wet, sr, *mode, (*cutoff_hz, *resonance),
+                    *floww_index, (*env_oct, *key_track, *vel_amount), conf, voice, svfl, svfr
+                );
+            },
+            Self::Reverb { reverb } => {
+                reverb_gen(buf, len, wet, reverb);
+            },
+            Self::Convolver { sample_index, ir_key, convolvers } => {
+                convolver_gen(buf, sb, len, wet, *sample_index, ir_key, convolvers);
+            },
+            Self::Delay { delay } => {
+                delay_gen(buf, len, wet, delay);
+            },
+            Self::Chorus { chorus } => {
+                mod_delay_gen(buf, len, wet, chorus);
+            },
+            Self::Flanger { flanger } => {
+                mod_delay_gen(buf, len, wet, flanger);
+            },
+            Self::Phaser { phaser } => {
+                phaser_gen(buf, len, wet, phaser);
+            },
+            Self::Distortion { distortion } => {
+                distortion_gen(buf, len, wet, distortion);
+            },
+            Self::Noise { floww_index, adsr, noise, notes, .. } => {
+                noise_gen(buf, fb, len, *floww_index, adsr, noise, notes, sr);
+            },
+            Self::Fm { floww_index, conf, notes } => {
+                fm_gen(buf, fb, len, *floww_index, conf, notes, sr);
+            },
+            Self::Va { floww_index, conf, notes } => {
+                va_gen(buf, fb, len, *floww_index, conf, notes, sr);
+            },
+            Self::Pluck { floww_index, conf, seed, t, notes } => {
+                pluck_gen(buf, fb, len, *floww_index, conf, *seed, *t, notes, sr);
+                *t += len;
+            },
+            Self::Drum { floww_index, note, conf, t, hits } => {
+                drum_gen(buf, fb, len, *floww_index, *note, conf, *t, hits, sr);
+                *t += len;
+            },
+            Self::Sampler { sample_index, floww_index, conf, notes } => {
+                sampler_gen(buf, sb, fb, len, *sample_index, *floww_index, conf, notes, sr);
+            },
+            Self::Instrument { floww_index, zones, hits, notes } => {
+                instrument_gen(buf, sb, fb, len, *floww_index, zones, hits, notes, sr);
+            },
+            Self::Granular {
+                sample_index, floww_index, position_floww_index, granular, notes
+            } => {
+                granular_gen(
+                    buf, sb, fb, len, *sample_index, *floww_index, *position_floww_index, granular,
+                    notes, sr
+                );
+            },
+            Self::Slicer {
+                sample_index, floww_index, base_note, slices, gains, reverse, playing
+            } => {
+                slicer_gen(
+                    buf, sb, fb, len, *sample_index, *floww_index, *base_note, slices, gains,
+                    reverse, playing
+                );
+            },
+            Self::StreamLoop { reader } => {
+                for i in 0..len{
+                    (buf.l[i], buf.r[i]) = reader.next();
+                }
+            },
+            Self::Clip { source, conf, t } => {
+                clip_gen(buf, sb, len, source, conf, t);
+            },
         }
         buf.apply_angle(angle, len);
         buf.apply_gain(gain, len);
@@ -277,6 +729,25 @@ impl VertexExt{
             Self::Lv2fx { .. } => true,
             Self::Adsr { .. } => true,
             Self::BandPass { .. } => true,
+            Self::Filter { .. } => true,
+            Self::Reverb { .. } => true,
+            Self::Convolver { .. } => true,
+            Self::Delay { .. } => true,
+            Self::Chorus { .. } => true,
+            Self::Flanger { .. } => true,
+            Self::Phaser { .. } => true,
+            Self::Distortion { .. } => true,
+            Self::Noise { .. } => false,
+            Self::Fm { .. } => false,
+            Self::Va { .. } => false,
+            Self::Pluck { .. } => false,
+            Self::Drum { .. } => false,
+            Self::Sampler { .. } => false,
+            Self::Instrument { .. } => false,
+            Self::Granular { .. } => false,
+            Self::Slicer { .. } => false,
+            Self::StreamLoop { .. } => false,
+            Self::Clip { .. } => false,
         }
     }
 
@@ -318,6 +789,48 @@ fn sum_inputs(buf: &mut Sample, len: usize, res: Vec<&Sample>){
     }
 }
 
+// Note on/off handling shared by the polyphonic vertices.
+// notes are (note, vel, env_t, rel_t, state), init creates the state from the note and velocity.
+fn poly_note_events<S>(
+    fb: &mut FlowwBank, floww_index: usize, i: usize, sr: usize,
+    notes: &mut Vec<(f32, f32, f32, f32, S)>, mut init: impl FnMut(f32, f32) -> S
+){
+    for (on, note, vel) in fb.get_block_simple(floww_index, i){
+        if on{
+            let state = init(note, vel);
+            notes.push((note, vel, -(i as f32 / sr as f32), 0.0, state));
+        } else {
+            notes.retain(|x| (x.0 - note).abs() > 0.001 || x.3 == 0.0);
+            for (n, _, env_t, rel_t, _) in notes.iter_mut(){
+                if (*n - note).abs() > 0.001 { continue; }
+                if *rel_t == 0.0{
+                    *rel_t = *env_t + (i as f32 / sr as f32);
+                    *env_t = -(i as f32 / sr as f32);
+                } else {
+                    panic!("Synth: impossible release stage note");
+                }
+            }
+        }
+    }
+}
+
+#[inline]
+fn poly_env(adsr: &AdsrConf, env_t: f32, rel_t: f32, i: usize, sr: usize) -> f32{
+    let env_time = env_t + (i as f32 / sr as f32);
+    if rel_t == 0.0 { apply_ads(adsr, env_time) }
+    else { apply_r_rt(adsr, env_time, rel_t) }
+}
+
+// move the envelopes a block forward and drop the notes that are done releasing
+fn poly_notes_next_block<S>(
+    notes: &mut Vec<(f32, f32, f32, f32, S)>, len: usize, sr: usize, release_sec: f32
+){
+    for (_, _, env_t, _, _) in notes.iter_mut(){
+        *env_t += len as f32 / sr as f32;
+    }
+    notes.retain(|x| x.3 == 0.0 || x.2 <= release_sec);
+}
+
 fn normalize_gen(buf: &mut Sample, len: usize, max: &mut f32, scan_max: &mut f32, is_scan: bool){
     let buf_max = buf.scan_max(len);
     if is_scan{
@@ -332,10 +845,13 @@ fn sample_loop_gen(
     buf: &mut Sample, sb: &SampleBank, len: usize, t: &mut usize, sample_index: usize
 ){
     let sample = sb.get_sample(sample_index);
-    let l = sample.len();
+    // play up to the end of the loop once, then keep looping
+    let (start, end) = sb.get_loop(sample_index).unwrap_or((0, sample.len()));
     for i in 0..len{
-        buf.l[i] = sample.l[(*t + i) % l];
-        buf.r[i] = sample.r[(*t + i) % l];
+        let pos = *t + i;
+        let pos = if pos < end { pos } else { start + (pos - start) % (end - start) };
+        buf.l[i] = sample.l[pos];
+        buf.r[i] = sample.r[pos];
     }
     *t += len;
 }
@@ -459,8 +975,8 @@ fn debug_sine_gen(
 #[allow(clippy::too_many_arguments)]
 fn synth_gen(
     buf: &mut Sample, fb: &mut FlowwBank, len: usize, floww_index: usize,
-    notes: &mut Vec<(f32, f32, f32, f32)>, square: &OscConf, topflat: &OscConf, triangle: &OscConf,
-    t: usize, sr: usize
+    notes: &mut Vec<(f32, f32, f32, f32, ())>, square: &OscConf, topflat: &OscConf,
+    triangle: &OscConf, t: usize, sr: usize
 ){
     let osc_amp_multiplier = 1.0 / (
         square.volume * square.adsr.max_vel() +
@@ -478,32 +994,15 @@ fn synth_gen(
     }
     fb.start_block(floww_index);
     for i in 0..len{
-        for (on, note, vel) in fb.get_block_simple(floww_index, i){
-            if on{
-                notes.push((note, vel, -(i as f32 / sr as f32), 0.0));
-            } else {
-                notes.retain(|x| (x.0 - note).abs() > 0.001 || x.3 == 0.0);
-                for (n, _, env_t, rel_t) in notes.iter_mut(){
-                    if (*n - note).abs() > 0.001 { continue; }
-                    if *rel_t == 0.0{
-                        *rel_t = *env_t + (i as f32 / sr as f32);
-                        *env_t = -(i as f32 / sr as f32);
-                    } else {
-                        panic!("Synth: impossible release stage note");
-                    }
-                }
-            }
-        }
+        poly_note_events(fb, floww_index, i, sr, notes, |_, _| ());
 
         buf.l[i] = 0.0;
         buf.r[i] = 0.0;
-        for (note, vel, env_t, rel_t) in notes.iter(){
+        for (note, vel, env_t, rel_t, _) in notes.iter(){
             let time = (t + i) as f32 / sr as f32;
-            let env_time = env_t + (i as f32 / sr as f32);
             let hz = 440.0 * (2.0f32).powf((note - 69.0) / 12.0);
 
-            let env_vel = |adsr_conf| if *rel_t == 0.0 { apply_ads(adsr_conf, env_time) }
-            else { apply_r_rt(adsr_conf, env_time, *rel_t) };
+            let env_vel = |adsr_conf| poly_env(adsr_conf, *env_t, *rel_t, i, sr);
 
             let mut s = 0.0;
             if square.volume > 0.0 {
@@ -522,10 +1021,7 @@ fn synth_gen(
             buf.r[i] += s;
         }
     }
-    for (_, _, env_t, _) in notes.iter_mut(){
-        *env_t += len as f32 / sr as f32;
-    }
-    notes.retain(|x| x.3 == 0.0 || x.2 <= release_sec);
+    poly_notes_next_block(notes, len, sr, release_sec);
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -537,23 +1033,7 @@ fn sampsyn_gen(
     let amp_multiplier = 1.0 / adsr.max_vel();
     fb.start_block(floww_index);
     for i in 0..len{
-        for (on, note, vel) in fb.get_block_simple(floww_index, i){
-            if on{
-                let init_state = initial_state(wave_table, 0.0);
-                notes.push((note, vel, -(i as f32 / sr as f32), 0.0, init_state));
-            } else {
-                notes.retain(|x| (x.0 - note).abs() > 0.001 || x.3 == 0.0);
-                for (n, _, env_t, rel_t, _) in notes.iter_mut(){
-                    if (*n - note).abs() > 0.001 { continue; }
-                    if *rel_t == 0.0{
-                        *rel_t = *env_t + (i as f32 / sr as f32);
-                        *env_t = -(i as f32 / sr as f32);
-                    } else {
-                        panic!("Synth: impossible release stage note");
-                    }
-                }
-            }
-        }
+        poly_note_events(fb, floww_index, i, sr, notes, |_, _| initial_state(wave_table, 0.0));
 
         buf.l[i] = 0.0;
         buf.r[i] = 0.0;
@@ -561,20 +1041,14 @@ fn sampsyn_gen(
             let env_time = *env_t + (i as f32 / sr as f32);
             let hz = 440.0 * (2.0f32).powf((*note - 69.0) / 12.0);
 
-            let env_vel = |adsr_conf| if *rel_t == 0.0 { apply_ads(adsr_conf, env_time) }
-            else { apply_r_rt(adsr_conf, env_time, *rel_t) };
-
             let mut s = 0.0;
-            let vel = *vel * env_vel(adsr) * amp_multiplier;
+            let vel = *vel * poly_env(adsr, *env_t, *rel_t, i, sr) * amp_multiplier;
             s += wavetable_act_state(wave_table, state, hz, env_time + *rel_t, sr as f32) * vel;
             buf.l[i] += s;
             buf.r[i] += s;
         }
     }
-    for (_, _, env_t, _, _) in notes.iter_mut(){
-        *env_t += len as f32 / sr as f32;
-    }
-    notes.retain(|x| x.3 == 0.0 || x.2 <= adsr.release_sec);
+    poly_notes_next_block(notes, len, sr, adsr.release_sec);
 }
 
 #[cfg(feature = "lv2")]
@@ -688,3 +1162,473 @@ fn band_pass_gen(buf: &mut Sample, len: usize, wet: f32, first: &mut bool, pass:
     }
 }
 
+
+// cutoff: (cutoff_hz, resonance), amounts: (env_oct, key_track, vel_amount)
+#[allow(clippy::too_many_arguments)]
+fn filter_gen(
+    buf: &mut Sample, len: usize, fb: &mut FlowwBank, wet: f32, sr: usize, mode: SvfMode,
+    cutoff: (f32, f32), floww_index: Option<usize>, amounts: (f32, f32, f32), conf: &AdsrConf,
+    voice: &mut Option<(f32, f32, f32, f32)>, svfl: &mut Svf, svfr: &mut Svf
+){
+    if wet < 0.0001 { return; }
+    let (cutoff_hz, resonance) = cutoff;
+    let (env_oct, key_track, vel_amount) = amounts;
+    if let Some(index) = floww_index{
+        fb.start_block(index);
+    }
+    for i in 0..len{
+        let offset = i as f32 / sr as f32;
+        if let Some(index) = floww_index{
+            // last note priority: a new note retriggers the envelope, releasing it starts the
+            // release stage of the envelope
+            for (on, note, vel) in fb.get_block_simple(index, i){
+                if on{
+                    *voice = Some((note, vel, -offset, 0.0));
+                } else if let Some((n, _, env_t, rel_t)) = voice{
+                    if (*n - note).abs() > 0.001 || *rel_t != 0.0 { continue; }
+                    *rel_t = *env_t + offset;
+                    *env_t = -offset;
+                }
+            }
+        }
+        let oct = if let Some((note, vel, env_t, rel_t)) = voice{
+            let env_time = *env_t + offset;
+            let env = if *rel_t == 0.0 { apply_ads(conf, env_time) }
+            else { apply_r_rt(conf, env_time, *rel_t) };
+            env * env_oct * lerp(1.0, *vel, vel_amount) + key_track * (*note - 60.0) / 12.0
+        } else {
+            0.0
+        };
+        let coefs = SvfCoefs::new(cutoff_hz * 2.0f32.powf(oct), resonance, sr);
+        let l = buf.l[i];
+        let r = buf.r[i];
+        buf.l[i] = lerp(l, svfl.process(l, &coefs, mode), wet);
+        buf.r[i] = lerp(r, svfr.process(r, &coefs, mode), wet);
+    }
+    if let Some((_, _, env_t, _)) = voice{
+        *env_t += len as f32 / sr as f32;
+    }
+}
+
+fn reverb_gen(buf: &mut Sample, len: usize, wet: f32, reverb: &mut Reverb){
+    if wet < 0.0001 { return; }
+    for i in 0..len{
+        let ll = buf.l[i];
+        let rr = buf.r[i];
+        let (l, r) = reverb.process(ll, rr);
+        buf.l[i] = lerp(ll, l, wet);
+        buf.r[i] = lerp(rr, r, wet);
+    }
+}
+
+fn convolver_gen(
+    buf: &mut Sample, sb: &SampleBank, len: usize, wet: f32, sample_index: usize,
+    ir_key: &mut (usize, usize), convolvers: &mut Vec<Convolver>
+){
+    if wet < 0.0001 { return; }
+    let ir = sb.get_sample(sample_index);
+    // (re)build the partitions when the ir in the bank has been swapped out or the block changed
+    let key = (ir.l.as_ptr() as usize, ir.len());
+    if convolvers.is_empty() || *ir_key != key || convolvers[0].block() != len{
+        // scale the ir to unit energy so long tails don't blow up the level
+        let energy = |s: &[f32]| s.iter().map(|x| x * x).sum::<f32>();
+        let energy = energy(&ir.l).max(energy(&ir.r));
+        let scale = if energy > 0.0 { 1.0 / energy.sqrt() } else { 0.0 };
+        let mut planner = RealFftPlanner::new();
+        *convolvers = [&ir.l, &ir.r].iter().map(|channel| {
+            let scaled = channel.iter().map(|x| x * scale).collect::<Vec<_>>();
+            Convolver::new(&scaled, len, &mut planner)
+        }).collect();
+        *ir_key = key;
+    }
+    convolvers[0].process(&mut buf.l[..len], wet);
+    convolvers[1].process(&mut buf.r[..len], wet);
+}
+
+fn delay_gen(buf: &mut Sample, len: usize, wet: f32, delay: &mut StereoDelay){
+    if wet < 0.0001 { return; }
+    for i in 0..len{
+        let (l, r) = delay.process(buf.l[i], buf.r[i]);
+        buf.l[i] += l * wet;
+        buf.r[i] += r * wet;
+    }
+}
+
+fn mod_delay_gen(buf: &mut Sample, len: usize, wet: f32, mod_delay: &mut ModDelay){
+    if wet < 0.0001 { return; }
+    for i in 0..len{
+        let ll = buf.l[i];
+        let rr = buf.r[i];
+        let (l, r) = mod_delay.process(ll, rr);
+        buf.l[i] = lerp(ll, l, wet);
+        buf.r[i] = lerp(rr, r, wet);
+    }
+}
+
+fn phaser_gen(buf: &mut Sample, len: usize, wet: f32, phaser: &mut Phaser){
+    if wet < 0.0001 { return; }
+    for i in 0..len{
+        let ll = buf.l[i];
+        let rr = buf.r[i];
+        let (l, r) = phaser.process(ll, rr);
+        buf.l[i] = lerp(ll, l, wet);
+        buf.r[i] = lerp(rr, r, wet);
+    }
+}
+
+fn distortion_gen(buf: &mut Sample, len: usize, wet: f32, distortion: &mut Distortion){
+    if wet < 0.0001 { return; }
+    for i in 0..len{
+        (buf.l[i], buf.r[i]) = distortion.process(buf.l[i], buf.r[i], wet);
+    }
+}
+
+// left and right get their own stream, reseeded on every jump in time so a render from a given
+// start time is repeatable
+fn noise_pair(color: NoiseColor, seed: u64, time: usize) -> [Noise; 2]{
+    let seed = seed.wrapping_mul(2) ^ (time as u64).rotate_left(32);
+    [Noise::new(color, seed), Noise::new(color, seed.wrapping_add(1))]
+}
+
+#[allow(clippy::too_many_arguments)]
+fn noise_gen(
+    buf: &mut Sample, fb: &mut FlowwBank, len: usize, floww_index: Option<usize>,
+    adsr: &AdsrConf, noise: &mut [Noise; 2], notes: &mut Vec<(f32, f32, f32, f32, ())>, sr: usize
+){
+    let floww_index = if let Some(index) = floww_index { index }
+    else {
+        for i in 0..len{
+            buf.l[i] = noise[0].next();
+            buf.r[i] = noise[1].next();
+        }
+        return;
+    };
+    let amp_multiplier = 1.0 / adsr.max_vel();
+    fb.start_block(floww_index);
+    for i in 0..len{
+        poly_note_events(fb, floww_index, i, sr, notes, |_, _| ());
+        let mut amp = 0.0;
+        for (_, vel, env_t, rel_t, _) in notes.iter(){
+            amp += vel * poly_env(adsr, *env_t, *rel_t, i, sr) * amp_multiplier;
+        }
+        // keep the streams running while silent so the noise does not depend on the notes
+        buf.l[i] = noise[0].next() * amp;
+        buf.r[i] = noise[1].next() * amp;
+    }
+    poly_notes_next_block(notes, len, sr, adsr.release_sec);
+}
+
+fn fm_gen(
+    buf: &mut Sample, fb: &mut FlowwBank, len: usize, floww_index: usize, conf: &FmConf,
+    notes: &mut Vec<(f32, f32, f32, f32, FmVoice)>, sr: usize
+){
+    let amp_multiplier = conf.amp_multiplier();
+    fb.start_block(floww_index);
+    for i in 0..len{
+        poly_note_events(fb, floww_index, i, sr, notes, |_, _| FmVoice::default());
+
+        buf.l[i] = 0.0;
+        buf.r[i] = 0.0;
+        for (note, vel, env_t, rel_t, voice) in notes.iter_mut(){
+            let hz = 440.0 * (2.0f32).powf((*note - 69.0) / 12.0);
+            let mut envs = [0.0; MAX_OPERATORS];
+            for (env, op) in envs.iter_mut().zip(conf.ops.iter()){
+                *env = poly_env(&op.adsr, *env_t, *rel_t, i, sr);
+            }
+            let s = conf.sample(voice, hz, &envs, sr) * *vel * amp_multiplier;
+            buf.l[i] += s;
+            buf.r[i] += s;
+        }
+    }
+    poly_notes_next_block(notes, len, sr, conf.release_sec());
+}
+
+fn va_gen(
+    buf: &mut Sample, fb: &mut FlowwBank, len: usize, floww_index: usize, conf: &VaConf,
+    notes: &mut Vec<(f32, f32, f32, f32, VaVoice)>, sr: usize
+){
+    let amp_multiplier = 1.0 / conf.adsr.max_vel();
+    fb.start_block(floww_index);
+    for i in 0..len{
+        poly_note_events(fb, floww_index, i, sr, notes, |_, _| VaVoice::new(conf.unison));
+
+        buf.l[i] = 0.0;
+        buf.r[i] = 0.0;
+        for (note, vel, env_t, rel_t, voice) in notes.iter_mut(){
+            let hz = 440.0 * (2.0f32).powf((*note - 69.0) / 12.0);
+            let amp = *vel * poly_env(&conf.adsr, *env_t, *rel_t, i, sr) * amp_multiplier;
+            let (l, r) = conf.sample(voice, hz, sr);
+            buf.l[i] += l * amp;
+            buf.r[i] += r * amp;
+        }
+    }
+    poly_notes_next_block(notes, len, sr, conf.adsr.release_sec);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn pluck_gen(
+    buf: &mut Sample, fb: &mut FlowwBank, len: usize, floww_index: usize, conf: &PluckConf,
+    seed: u64, t: usize, notes: &mut Vec<(f32, f32, f32, f32, PluckVoice)>, sr: usize
+){
+    fb.start_block(floww_index);
+    for i in 0..len{
+        // the burst is seeded by the frame it starts on so renders don't depend on where
+        // playback started
+        let note_seed = seed.wrapping_mul(2) ^ ((t + i) as u64).rotate_left(32);
+        poly_note_events(fb, floww_index, i, sr, notes, |note, vel| {
+            let hz = 440.0 * (2.0f32).powf((note - 69.0) / 12.0);
+            PluckVoice::new(conf, hz, vel, note_seed ^ note.to_bits() as u64, sr)
+        });
+
+        buf.l[i] = 0.0;
+        buf.r[i] = 0.0;
+        for (_, _, _, rel_t, voice) in notes.iter_mut(){
+            let (l, r) = voice.next(conf, *rel_t != 0.0);
+            buf.l[i] += l;
+            buf.r[i] += r;
+        }
+    }
+    poly_notes_next_block(notes, len, sr, conf.release_sec);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn drum_gen(
+    buf: &mut Sample, fb: &mut FlowwBank, len: usize, floww_index: usize,
+    target_note: Option<usize>, conf: &DrumConf, t: usize, hits: &mut Vec<DrumHit>, sr: usize
+){
+    fb.start_block(floww_index);
+    for i in 0..len{
+        if let Some((note, v)) = fb.get_block_drum(floww_index, i){
+            let ok = if let Some(n) = target_note{
+                (note - n as f32).abs() < 0.01
+            }
+            else { true };
+            if ok{
+                hits.push(DrumHit::new(conf, v, (t + i) as u64, sr));
+            }
+        }
+        let mut s = 0.0;
+        for hit in hits.iter_mut(){
+            s += hit.next(conf, sr);
+        }
+        buf.l[i] = s;
+        buf.r[i] = s;
+    }
+    hits.retain(|hit| !hit.done(conf, sr));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn sampler_gen(
+    buf: &mut Sample, sb: &SampleBank, fb: &mut FlowwBank, len: usize, sample_index: usize,
+    floww_index: usize, conf: &SamplerConf, notes: &mut Vec<(f32, f32, f32, f32, f64)>, sr: usize
+){
+    let sample = sb.get_sample(sample_index);
+    let slen = sample.len();
+    let lp = conf.loop_frames(slen, sr);
+    let amp_multiplier = if let Some(adsr) = &conf.adsr { 1.0 / adsr.max_vel() } else { 1.0 };
+    fb.start_block(floww_index);
+    for i in 0..len{
+        poly_note_events(fb, floww_index, i, sr, notes, |_, _| 0.0);
+
+        buf.l[i] = 0.0;
+        buf.r[i] = 0.0;
+        for (note, vel, env_t, rel_t, pos) in notes.iter_mut(){
+            let amp = if let Some(adsr) = &conf.adsr{
+                poly_env(adsr, *env_t, *rel_t, i, sr) * amp_multiplier
+            } else if *rel_t == 0.0{
+                1.0
+            } else {
+                // gate: a short fade after the note off
+                let env_time = *env_t + (i as f32 / sr as f32);
+                (1.0 - env_time / GATE_RELEASE_SEC).max(0.0)
+            };
+            let ratio = 2.0f32.powf((*note - conf.root_note) / 12.0);
+            if lp.is_some() || (*pos as usize) < slen{
+                let read = |data: &[f32]| read_interpolated(
+                    data, *pos, ratio, conf.interpolation, lp
+                );
+                buf.l[i] += read(&sample.l) * *vel * amp;
+                buf.r[i] += read(&sample.r) * *vel * amp;
+            }
+            *pos += ratio as f64;
+            if let Some((start, end)) = lp{
+                if *pos >= end as f64{
+                    *pos = start as f64 + (*pos - start as f64) % (end - start) as f64;
+                }
+            }
+        }
+    }
+    poly_notes_next_block(notes, len, sr, conf.release_sec());
+    // one shots are done when they run out of sample
+    if lp.is_none(){
+        notes.retain(|x| (x.4 as usize) < slen + 8);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn instrument_gen(
+    buf: &mut Sample, sb: &SampleBank, fb: &mut FlowwBank, len: usize, floww_index: usize,
+    zones: &[(Zone, usize)], hits: &mut [usize], notes: &mut Vec<InstrumentNote>, sr: usize
+){
+    fb.start_block(floww_index);
+    for i in 0..len{
+        poly_note_events(fb, floww_index, i, sr, notes, |note, vel| {
+            let key = (note.round().max(0.0) as usize).min(hits.len() - 1);
+            let hit = hits[key];
+            hits[key] += 1;
+            zones.iter().enumerate()
+                .filter(|(_, (zone, _))| zone.matches(note, vel, hit))
+                .map(|(j, (zone, _))| (j, zone.offset as f64))
+                .collect()
+        });
+
+        buf.l[i] = 0.0;
+        buf.r[i] = 0.0;
+        for (note, vel, env_t, rel_t, voices) in notes.iter_mut(){
+            let env_time = *env_t + (i as f32 / sr as f32);
+            for (j, pos) in voices.iter_mut(){
+                let (zone, sample_index) = &zones[*j];
+                let sample = sb.get_sample(*sample_index);
+                let released = *rel_t != 0.0 && zone.loop_mode != LoopMode::OneShot;
+                let amp = if released { apply_r_rt(&zone.adsr, env_time, *rel_t) }
+                    // one shots keep going, since the note off their time is split up
+                    else { apply_ads(&zone.adsr, env_time + *rel_t) };
+                let lp = match zone.loop_mode{
+                    LoopMode::Continuous => zone.loop_frames,
+                    LoopMode::Sustain if !released => zone.loop_frames,
+                    _ => None,
+                };
+                let ratio = zone.ratio(*note);
+                if lp.is_some() || (*pos as usize) < sample.len(){
+                    let read = |data: &[f32]| read_interpolated(
+                        data, *pos, ratio, Interpolation::Cubic, lp
+                    );
+                    let amp = amp * *vel * zone.gain;
+                    buf.l[i] += read(&sample.l) * amp * (1.0 - zone.pan).min(1.0);
+                    buf.r[i] += read(&sample.r) * amp * (1.0 + zone.pan).min(1.0);
+                }
+                *pos += ratio as f64;
+                if let Some((start, end)) = lp{
+                    if *pos >= end as f64{
+                        *pos = start as f64 + (*pos - start as f64) % (end - start) as f64;
+                    }
+                }
+            }
+        }
+    }
+    for (_, _, env_t, rel_t, voices) in notes.iter_mut(){
+        *env_t += len as f32 / sr as f32;
+        voices.retain(|(j, pos)| {
+            let zone = &zones[*j].0;
+            let released = *rel_t != 0.0 && zone.loop_mode != LoopMode::OneShot;
+            if released && *env_t > zone.adsr.release_sec { return false; }
+            let looping = zone.loop_frames.is_some() && match zone.loop_mode{
+                LoopMode::Continuous => true,
+                LoopMode::Sustain => !released,
+                _ => false,
+            };
+            looping || (*pos as usize) < sb.get_sample(zones[*j].1).len()
+        });
+    }
+    notes.retain(|x| !x.4.is_empty());
+}
+
+#[allow(clippy::too_many_arguments)]
+fn granular_gen(
+    buf: &mut Sample, sb: &SampleBank, fb: &mut FlowwBank, len: usize, sample_index: usize,
+    floww_index: Option<usize>, position_floww_index: Option<usize>, granular: &mut Granular,
+    notes: &mut Vec<(f32, f32)>, sr: usize
+){
+    let sample = sb.get_sample(sample_index);
+    // the floww bank reads one floww at a time, so get the position changes up front
+    let mut positions = Vec::new();
+    if let Some(index) = position_floww_index{
+        fb.start_block(index);
+        for i in 0..len{
+            for (on, note, _) in fb.get_block_simple(index, i){
+                if on { positions.push((i, note)); }
+            }
+        }
+    }
+    let mut positions = positions.into_iter().peekable();
+    if let Some(index) = floww_index { fb.start_block(index); }
+    for i in 0..len{
+        while let Some((_, note)) = positions.next_if(|(at, _)| *at == i){
+            granular.set_position_note(note);
+        }
+        let (l, r) = if let Some(index) = floww_index{
+            for (on, note, vel) in fb.get_block_simple(index, i){
+                if on{
+                    notes.push((note, vel));
+                } else {
+                    notes.retain(|(n, _)| (n - note).abs() > 0.001);
+                }
+            }
+            granular.next(sample, Some(notes), sr)
+        } else {
+            granular.next(sample, None, sr)
+        };
+        buf.l[i] = l;
+        buf.r[i] = r;
+    }
+}
+
+// frames faded in and out at both ends of a slice so cutting into a sound does not click
+const SLICE_FADE: usize = 64;
+
+#[allow(clippy::too_many_arguments)]
+fn slicer_gen(
+    buf: &mut Sample, sb: &SampleBank, fb: &mut FlowwBank, len: usize, sample_index: usize,
+    floww_index: usize, base_note: f32, slices: &Slices, gains: &[f32], reverse: &[bool],
+    playing: &mut Vec<(usize, usize, f32)>
+){
+    let sample = sb.get_sample(sample_index);
+    fb.start_block(floww_index);
+    for i in 0..len{
+        // every hit plays, slices on the same frame included
+        for (on, note, vel) in fb.get_block_simple(floww_index, i){
+            let slice = (note - base_note).round();
+            if on && slice >= 0.0 && (slice as usize) < slices.len(){
+                playing.push((slice as usize, 0, vel));
+            }
+        }
+        let (mut l, mut r) = (0.0, 0.0);
+        for (slice, t, vel) in playing.iter_mut(){
+            let (start, end) = slices[*slice];
+            let slice_len = end - start;
+            let pos = if reverse.get(*slice).copied().unwrap_or(false) { end - 1 - *t }
+                else { start + *t };
+            // a reversed slice starts on the loud end, so the start is faded as well
+            let fade = ((*t + 1).min(slice_len - *t) as f32 / SLICE_FADE as f32).min(1.0);
+            let amp = *vel * gains.get(*slice).copied().unwrap_or(1.0) * fade;
+            l += sample.l[pos] * amp;
+            r += sample.r[pos] * amp;
+            *t += 1;
+        }
+        playing.retain(|(slice, t, _)| *t < slices[*slice].1 - slices[*slice].0);
+        buf.l[i] = l;
+        buf.r[i] = r;
+    }
+}
+
+fn clip_gen(
+    buf: &mut Sample, sb: &SampleBank, len: usize, source: &mut ClipSource, conf: &ClipConf,
+    t: &mut usize
+){
+    for i in 0..len{
+        (buf.l[i], buf.r[i]) = if let Some(pos) = conf.position(*t + i){
+            let (l, r) = match source{
+                ClipSource::Memory(index) => {
+                    let sample = sb.get_sample(*index);
+                    (sample.l[conf.offset + pos], sample.r[conf.offset + pos])
+                },
+                ClipSource::Stream(reader) => reader.next(),
+            };
+            let amp = conf.amp(pos);
+            (l * amp, r * amp)
+        } else {
+            (0.0, 0.0)
+        };
+    }
+    *t += len;
+}
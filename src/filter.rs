@@ -0,0 +1,109 @@
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SvfMode{ Low, Band, High, Notch }
+
+impl SvfMode{
+    pub fn from(string: &str) -> Self{
+        match string{
+            "band" | "bandpass" | "bp" => SvfMode::Band,
+            "high" | "highpass" | "hp" => SvfMode::High,
+            "notch" => SvfMode::Notch,
+            _ => SvfMode::Low,
+        }
+    }
+}
+
+// Trapezoidal integrated state variable filter (Andrew Simper / Cytomic)
+// https://cytomic.com/files/dsp/SvfLinearTrapOptimised2.pdf
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Svf{
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SvfCoefs{
+    k: f32,
+    a1: f32,
+    a2: f32,
+    a3: f32,
+}
+
+impl SvfCoefs{
+    // resonance goes from zero (no resonance) to one (self oscillation)
+    pub fn new(cutoff_hz: f32, resonance: f32, sr: usize) -> Self{
+        let fc = cutoff_hz.clamp(10.0, sr as f32 * 0.49);
+        let g = (PI * fc / sr as f32).tan();
+        let k = 2.0 - 1.98 * resonance.clamp(0.0, 1.0);
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+        Self{ k, a1, a2, a3 }
+    }
+}
+
+impl Svf{
+    pub fn reset(&mut self){
+        self.ic1eq = 0.0;
+        self.ic2eq = 0.0;
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32, c: &SvfCoefs, mode: SvfMode) -> f32{
+        let v3 = x - self.ic2eq;
+        let v1 = c.a1 * self.ic1eq + c.a2 * v3;
+        let v2 = self.ic2eq + c.a2 * self.ic1eq + c.a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+        match mode{
+            SvfMode::Low => v2,
+            SvfMode::Band => v1,
+            SvfMode::High => x - c.k * v1 - v2,
+            SvfMode::Notch => x - c.k * v1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::filter::*;
+
+    fn settle(mode: SvfMode, x: f32) -> f32{
+        let c = SvfCoefs::new(1000.0, 0.3, 48000);
+        let mut svf = Svf::default();
+        let mut y = 0.0;
+        for _ in 0..48000{
+            y = svf.process(x, &c, mode);
+        }
+        y
+    }
+
+    #[test]
+    fn svf_dc(){ // dc goes through low pass and notch, is blocked by band and high pass
+        assert!((1.0 - settle(SvfMode::Low, 1.0)).abs() < 0.001);
+        assert!((1.0 - settle(SvfMode::Notch, 1.0)).abs() < 0.001);
+        assert!(settle(SvfMode::Band, 1.0).abs() < 0.001);
+        assert!(settle(SvfMode::High, 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn svf_nyquist(){ // alternating signal is blocked by low pass and passed by high pass
+        let c = SvfCoefs::new(200.0, 0.0, 48000);
+        let mut low = Svf::default();
+        let mut high = Svf::default();
+        let mut lmax: f32 = 0.0;
+        let mut hmax: f32 = 0.0;
+        for i in 0..4800{
+            let x = if i % 2 == 0 { 1.0 } else { -1.0 };
+            let l = low.process(x, &c, SvfMode::Low);
+            let h = high.process(x, &c, SvfMode::High);
+            if i > 2400{
+                lmax = lmax.max(l.abs());
+                hmax = hmax.max(h.abs());
+            }
+        }
+        assert!(lmax < 0.01);
+        assert!(hmax > 0.99);
+    }
+}
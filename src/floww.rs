@@ -1,8 +1,11 @@
 use std::collections::HashMap;
 
 use floww::{ Floww, read_floww_from_midi, FlowwPacket, unpacket };
+use apres::{ MIDI, MIDIEvent };
 use zen_colour::*;
 
+use crate::instrument::DRUM_BANK;
+
 #[derive(Default)]
 pub struct FlowwBank{
     sr: usize,
@@ -13,6 +16,8 @@ pub struct FlowwBank{
     start_indices: Vec<usize>,
     names: HashMap<String, usize>,
     stream_list: Vec<usize>,
+    // (bank, program) of flowws loaded per midi channel
+    programs: HashMap<usize, (u16, u16)>,
 }
 
 impl FlowwBank{
@@ -27,6 +32,7 @@ impl FlowwBank{
         self.start_indices.clear();
         self.names.clear();
         self.stream_list.clear();
+        self.programs.clear();
     }
 
     fn declare_floww(&mut self, name: String, floww: Floww) -> usize{
@@ -47,6 +53,72 @@ impl FlowwBank{
         }
     }
 
+    // Splits a midi file into a floww per channel that has notes, named "name/1" to "name/16".
+    // Remembers the bank and program each channel starts with, channel 10 gets the drum bank.
+    pub fn add_midi_channels(&mut self, name: String, path: &str) -> Result<(), String>{
+        let midi = MIDI::from_path(path).map_err(|_| format!(
+            "{r}Could not read midi file: {b}\"{x}\"{r}.", r = RED, b = BLUE, x = path
+        ))?;
+        let ppqn = midi.get_ppqn().max(1) as f64;
+        // absolute ticks of all events, the tempo map is shared by all tracks
+        let mut events = Vec::new();
+        for track in midi.get_tracks(){
+            let mut tick = 0;
+            for (delta, id) in track{
+                tick += delta;
+                if let Some(ev) = midi.get_event(id){
+                    events.push((tick, ev));
+                }
+            }
+        }
+        events.sort_by_key(|(tick, _)| *tick);
+        // 120 bpm until the first tempo change, as the midi spec says
+        let (mut sec, mut last_tick, mut sec_per_tick) = (0.0f64, 0, 0.5 / ppqn);
+        let mut channels: Vec<Floww> = vec![Vec::new(); 16];
+        let mut banks = [0u16; 16];
+        let mut programs: [Option<(u16, u16)>; 16] = [None; 16];
+        for (tick, ev) in events{
+            sec += (tick - last_tick) as f64 * sec_per_tick;
+            last_tick = tick;
+            let t = sec as f32;
+            match ev{
+                MIDIEvent::SetTempo(us) => sec_per_tick = us as f64 / 1_000_000.0 / ppqn,
+                MIDIEvent::BankSelect(ch, bank) => banks[ch as usize & 15] = bank as u16,
+                MIDIEvent::ProgramChange(ch, program) => {
+                    let ch = ch as usize & 15;
+                    // only the program a channel starts its notes with
+                    if channels[ch].is_empty(){
+                        programs[ch] = Some((banks[ch], program as u16));
+                    }
+                },
+                MIDIEvent::NoteOn(ch, note, vel) => channels[ch as usize & 15]
+                    .push((note as usize, t, note as f32, vel as f32 / 127.0)),
+                MIDIEvent::NoteOff(ch, note, _) => channels[ch as usize & 15]
+                    .push((note as usize, t, note as f32, 0.0)),
+                _ => {},
+            }
+        }
+        for (ch, mut floww) in channels.into_iter().enumerate(){
+            if floww.is_empty() { continue; }
+            // note offs first so a note can end and start again on the same frame
+            floww.sort_by(|a, b| a.1.total_cmp(&b.1).then((a.3 > 0.0).cmp(&(b.3 > 0.0))));
+            let drums = ch == 9;
+            let program = match programs[ch]{
+                Some((_, program)) if drums => (DRUM_BANK, program),
+                Some(program) => program,
+                None => (if drums { DRUM_BANK } else { 0 }, 0),
+            };
+            let index = self.declare_floww(format!("{}/{}", name, ch + 1), floww);
+            self.programs.insert(index, program);
+        }
+        Ok(())
+    }
+
+    // (bank, program) of a floww loaded with add_midi_channels
+    pub fn get_program(&self, index: usize) -> Option<(u16, u16)>{
+        self.programs.get(&index).copied()
+    }
+
     pub fn declare_stream(&mut self, name: String){
         let index = self.declare_floww(name, vec![]);
         self.stream_list.push(index);
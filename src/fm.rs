@@ -0,0 +1,144 @@
+use crate::adsr::{ AdsrConf, build_adsr_conf };
+
+use std::f32::consts::PI;
+
+pub const MAX_OPERATORS: usize = 6;
+pub const MIN_OPERATORS: usize = 4;
+
+#[derive(Clone, Copy, Default)]
+pub struct FmOperator{
+    pub ratio: f32,
+    pub detune_cents: f32,
+    pub level: f32,
+    pub adsr: AdsrConf,
+}
+
+impl FmOperator{
+    // {ratio, detune_cents, level, adsr_conf...} where the adsr conf has 6 or 9 elements
+    pub fn from_arr(arr: &[f32]) -> Option<Self>{
+        if arr.len() < 3 { return None; }
+        Some(Self{
+            ratio: arr[0],
+            detune_cents: arr[1],
+            level: arr[2],
+            adsr: build_adsr_conf(&arr[3..])?,
+        })
+    }
+}
+
+// matrix[i][j]: how much operator j modulates operator i, the diagonal is self feedback.
+// outputs[i]: how much of operator i goes to the audio output.
+pub struct FmConf{
+    pub ops: Vec<FmOperator>,
+    pub matrix: [[f32; MAX_OPERATORS]; MAX_OPERATORS],
+    pub outputs: [f32; MAX_OPERATORS],
+}
+
+impl FmConf{
+    // Preset algorithms, they work for any amount of operators n, op 1 is index 0:
+    // 1: one stack, n -> .. -> 2 -> 1
+    // 2: two stacks, the second half of the operators on top of the first half
+    // 3: stacks of two: 2 -> 1, 4 -> 3, ..
+    // 4: the last operator modulates all others
+    // 5: all operators modulate the first one
+    // 6: all carriers, additive
+    // Feedback goes on the last operator.
+    pub fn algorithm(ops: Vec<FmOperator>, algorithm: usize, feedback: f32) -> Option<Self>{
+        let n = ops.len();
+        if !(MIN_OPERATORS..=MAX_OPERATORS).contains(&n) { return None; }
+        let mut matrix = [[0.0; MAX_OPERATORS]; MAX_OPERATORS];
+        let mut outputs = [0.0; MAX_OPERATORS];
+        match algorithm{
+            1 => {
+                for i in 0..n - 1 { matrix[i][i + 1] = 1.0; }
+                outputs[0] = 1.0;
+            },
+            2 => {
+                let half = n / 2;
+                for i in 0..n - 1{
+                    if i + 1 != half { matrix[i][i + 1] = 1.0; }
+                }
+                outputs[0] = 1.0;
+                outputs[half] = 1.0;
+            },
+            3 => {
+                for i in (0..n - 1).step_by(2) { matrix[i][i + 1] = 1.0; }
+                for i in (0..n).step_by(2) { outputs[i] = 1.0; }
+            },
+            4 => {
+                for i in 0..n - 1{
+                    matrix[i][n - 1] = 1.0;
+                    outputs[i] = 1.0;
+                }
+            },
+            5 => {
+                for m in matrix[0].iter_mut().take(n).skip(1) { *m = 1.0; }
+                outputs[0] = 1.0;
+            },
+            6 => {
+                for o in outputs.iter_mut().take(n) { *o = 1.0; }
+            },
+            _ => return None,
+        }
+        matrix[n - 1][n - 1] = feedback;
+        Some(Self{ ops, matrix, outputs })
+    }
+
+    // every row has n modulation amounts followed by the output amount
+    pub fn from_matrix(ops: Vec<FmOperator>, rows: &[Vec<f32>]) -> Option<Self>{
+        let n = ops.len();
+        if !(MIN_OPERATORS..=MAX_OPERATORS).contains(&n) { return None; }
+        if rows.len() != n || rows.iter().any(|row| row.len() != n + 1) { return None; }
+        let mut matrix = [[0.0; MAX_OPERATORS]; MAX_OPERATORS];
+        let mut outputs = [0.0; MAX_OPERATORS];
+        for (i, row) in rows.iter().enumerate(){
+            matrix[i][..n].copy_from_slice(&row[..n]);
+            outputs[i] = row[n];
+        }
+        Some(Self{ ops, matrix, outputs })
+    }
+
+    pub fn release_sec(&self) -> f32{
+        self.ops.iter().fold(0.0, |max, op| op.adsr.release_sec.max(max))
+    }
+
+    // scale so that the carriers at full envelope sum up to one
+    pub fn amp_multiplier(&self) -> f32{
+        let sum = self.ops.iter().zip(self.outputs.iter())
+            .map(|(op, out)| (op.level * out * op.adsr.max_vel()).abs())
+            .sum::<f32>();
+        if sum > 0.0 { 1.0 / sum } else { 0.0 }
+    }
+
+    // envs are the envelope values of the operators at this frame
+    #[inline]
+    pub fn sample(&self, voice: &mut FmVoice, hz: f32, envs: &[f32], sr: usize) -> f32{
+        let n = self.ops.len();
+        let mut outs = [0.0; MAX_OPERATORS];
+        // the modulators sit at the higher indices so compute from the top down, anything that
+        // has not been computed yet this frame (including feedback) uses the previous frame,
+        // averaged over two frames to tame the feedback
+        for i in (0..n).rev(){
+            let mut modulation = 0.0;
+            for (j, amount) in self.matrix[i].iter().enumerate().take(n){
+                if *amount == 0.0 { continue; }
+                let m = if j > i { outs[j] } else { (voice.prev[j] + voice.prev2[j]) * 0.5 };
+                modulation += amount * m;
+            }
+            let op = &self.ops[i];
+            outs[i] = ((voice.phases[i] + modulation) * 2.0 * PI).sin() * op.level * envs[i];
+            let inc = hz * op.ratio * 2.0f32.powf(op.detune_cents / 1200.0) / sr as f32;
+            voice.phases[i] = (voice.phases[i] + inc).fract();
+        }
+        voice.prev2 = voice.prev;
+        voice.prev = outs;
+        outs.iter().zip(self.outputs.iter()).take(n).map(|(o, w)| o * w).sum()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct FmVoice{
+    phases: [f32; MAX_OPERATORS],
+    prev: [f32; MAX_OPERATORS],
+    prev2: [f32; MAX_OPERATORS],
+}
@@ -0,0 +1,155 @@
+use crate::{
+    noise::Rng,
+    sample::Sample,
+    sampler::{ Interpolation, read_interpolated },
+};
+
+use std::f32::consts::{ PI, SQRT_2 };
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GrainWindow{ Hann, Triangle, Tukey, Gauss }
+
+impl GrainWindow{
+    pub fn from(string: &str) -> Self{
+        match string{
+            "triangle" => GrainWindow::Triangle,
+            "tukey" => GrainWindow::Tukey,
+            "gauss" | "gaussian" => GrainWindow::Gauss,
+            _ => GrainWindow::Hann,
+        }
+    }
+
+    // x goes from 0 to 1 over the grain
+    #[inline]
+    pub fn at(&self, x: f32) -> f32{
+        match self{
+            GrainWindow::Hann => 0.5 - 0.5 * (2.0 * PI * x).cos(),
+            GrainWindow::Triangle => 1.0 - (2.0 * x - 1.0).abs(),
+            // flat in the middle, cosine fades on the outer quarters
+            GrainWindow::Tukey => {
+                let edge = x.min(1.0 - x);
+                if edge >= 0.25 { 1.0 }
+                else { 0.5 - 0.5 * (PI * edge / 0.25).cos() }
+            },
+            GrainWindow::Gauss => (-0.5 * ((x - 0.5) / 0.15).powi(2)).exp(),
+        }
+    }
+}
+
+// position is where in the sample the grains start (0 to 1), scan_rate moves it along in
+// sample lengths per second. Jitter and spreads are random per grain.
+pub struct GranularConf{
+    pub grain_sec: f32,
+    pub density: f32,
+    pub position: f32,
+    pub scan_rate: f32,
+    pub jitter_sec: f32,
+    pub pitch_spread_cents: f32,
+    pub window: GrainWindow,
+    pub stereo_spread: f32,
+    pub seed: u64,
+}
+
+struct Grain{
+    pos: f64,
+    inc: f64,
+    age: usize,
+    len: usize,
+    amp: f32,
+    gains: (f32, f32),
+}
+
+pub struct Granular{
+    pub conf: GranularConf,
+    grains: Vec<Grain>,
+    rng: Rng,
+    acc: f32,
+    t: usize,
+    // position set by the position floww, overrides conf.position
+    position: Option<f32>,
+}
+
+// the note grains play at their original pitch
+const ROOT_NOTE: f32 = 60.0;
+
+impl Granular{
+    pub fn new(conf: GranularConf) -> Self{
+        let rng = Rng::new(conf.seed);
+        Self{
+            conf,
+            grains: Vec::new(),
+            rng,
+            acc: 0.0,
+            t: 0,
+            position: None,
+        }
+    }
+
+    pub fn set_time(&mut self, t: usize){
+        self.grains.clear();
+        self.rng = Rng::new(self.conf.seed ^ (t as u64).rotate_left(32));
+        self.acc = 0.0;
+        self.t = t;
+        self.position = None;
+    }
+
+    // note/127 becomes the position
+    pub fn set_position_note(&mut self, note: f32){
+        self.position = Some((note / 127.0).clamp(0.0, 1.0));
+    }
+
+    fn spawn(&mut self, len: usize, note: f32, vel: f32, sr: usize){
+        let c = &self.conf;
+        let base = self.position.unwrap_or(c.position)
+            + c.scan_rate * self.t as f32 / sr as f32;
+        let jitter = self.rng.next_bipolar() * c.jitter_sec * sr as f32;
+        let pos = (base.rem_euclid(1.0) * len as f32 + jitter).clamp(0.0, len as f32) as f64;
+        let cents = self.rng.next_bipolar() * c.pitch_spread_cents
+            + (note - ROOT_NOTE) * 100.0;
+        let pan = self.rng.next_bipolar() * c.stereo_spread.clamp(0.0, 1.0);
+        let angle = (pan + 1.0) * PI * 0.25;
+        self.grains.push(Grain{
+            pos,
+            inc: 2.0f64.powf(cents as f64 / 1200.0),
+            age: 0,
+            len: ((c.grain_sec * sr as f32) as usize).max(2),
+            amp: vel,
+            gains: (angle.cos() * SQRT_2, angle.sin() * SQRT_2),
+        });
+    }
+
+    // one frame, notes are the held (note, vel) or None to run freely
+    #[inline]
+    pub fn next(
+        &mut self, sample: &Sample, notes: Option<&[(f32, f32)]>, sr: usize
+    ) -> (f32, f32){
+        self.acc += self.conf.density.max(0.0) / sr as f32;
+        while self.acc >= 1.0{
+            self.acc -= 1.0;
+            match notes{
+                None => self.spawn(sample.len(), ROOT_NOTE, 1.0, sr),
+                Some(notes) => for (note, vel) in notes{
+                    self.spawn(sample.len(), *note, *vel, sr);
+                },
+            }
+        }
+        self.t += 1;
+        let (mut l, mut r) = (0.0, 0.0);
+        for g in self.grains.iter_mut(){
+            let w = self.conf.window.at(g.age as f32 / g.len as f32) * g.amp;
+            let ratio = g.inc as f32;
+            let read = |data: &[f32]| read_interpolated(
+                data, g.pos, ratio, Interpolation::Cubic, None
+            );
+            l += read(&sample.l) * w * g.gains.0;
+            r += read(&sample.r) * w * g.gains.1;
+            g.pos += g.inc;
+            g.age += 1;
+        }
+        self.grains.retain(|g| g.age < g.len);
+        // overlapping grains add up, keep the loudness about the same for every density
+        let overlap = (self.conf.density * self.conf.grain_sec).max(1.0);
+        let norm = 1.0 / overlap.sqrt();
+        (l * norm, r * norm)
+    }
+}
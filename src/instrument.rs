@@ -0,0 +1,226 @@
+use std::collections::{ HashMap, hash_map::Entry };
+
+use zen_colour::*;
+
+use crate::{
+    adsr::AdsrConf,
+    sample::SampleBank,
+    sfz::load_sfz,
+    sf2::load_sf2,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoopMode{
+    // play to the end of the sample or until released
+    None,
+    // play to the end of the sample, note offs are ignored
+    OneShot,
+    // loop until the note is done releasing
+    Continuous,
+    // loop until the note off, then play the rest of the sample while releasing
+    Sustain,
+}
+
+// A sample mapped onto a key and velocity range. Frames are in the project sample rate.
+#[derive(Clone)]
+pub struct Zone{
+    // name of the sample in the SampleBank
+    pub sample: String,
+    pub key_range: (f32, f32),
+    // from 0 to 1, like the floww velocities
+    pub vel_range: (f32, f32),
+    pub root_key: f32,
+    // how many semitones the pitch moves per key, 0 for drums
+    pub key_track: f32,
+    pub tune_cents: f32,
+    pub gain: f32,
+    // from -1 (left) to 1 (right)
+    pub pan: f32,
+    pub offset: usize,
+    pub loop_mode: LoopMode,
+    pub loop_frames: Option<(usize, usize)>,
+    pub adsr: AdsrConf,
+    // round robin: the zone plays on hit seq_position of every seq_length hits of a key
+    pub seq_length: usize,
+    pub seq_position: usize,
+}
+
+impl Default for Zone{
+    fn default() -> Self{
+        Self{
+            sample: String::new(),
+            key_range: (0.0, 127.0),
+            vel_range: (0.0, 1.0),
+            root_key: 60.0,
+            key_track: 1.0,
+            tune_cents: 0.0,
+            gain: 1.0,
+            pan: 0.0,
+            offset: 0,
+            loop_mode: LoopMode::None,
+            loop_frames: None,
+            adsr: AdsrConf::hit_conf(0.001, 0.0, 1.0, 0.0, 1.0, 0.001),
+            seq_length: 1,
+            seq_position: 1,
+        }
+    }
+}
+
+impl Zone{
+    // hit is how many times this key was hit before
+    pub fn matches(&self, note: f32, vel: f32, hit: usize) -> bool{
+        let note = note.round();
+        note >= self.key_range.0 && note <= self.key_range.1
+            && vel >= self.vel_range.0 - 0.0001 && vel <= self.vel_range.1 + 0.0001
+            && hit % self.seq_length.max(1) + 1 == self.seq_position
+    }
+
+    // playback speed for a note
+    #[inline]
+    pub fn ratio(&self, note: f32) -> f32{
+        2.0f32.powf(((note - self.root_key) * self.key_track + self.tune_cents / 100.0) / 12.0)
+    }
+
+    // convert frame positions from the sample rate of the file to the project sample rate
+    pub fn rescale_frames(&mut self, from: usize, to: usize){
+        if from == to || from == 0 { return; }
+        let conv = |x: usize| (x as f64 * to as f64 / from as f64).round() as usize;
+        self.offset = conv(self.offset);
+        self.loop_frames = self.loop_frames.map(|(a, b)| (conv(a), conv(b)));
+    }
+}
+
+pub struct Preset{
+    pub name: String,
+    pub bank: u16,
+    pub program: u16,
+    pub zones: Vec<Zone>,
+}
+
+// SFZ instruments have a single preset, SoundFonts have many
+pub struct Instrument{
+    pub presets: Vec<Preset>,
+    // the names of the samples this instrument put in the SampleBank
+    samples: Vec<String>,
+}
+
+// the bank SoundFonts use for General MIDI drum kits
+pub const DRUM_BANK: u16 = 128;
+
+impl Instrument{
+    // falls back to the same program in bank 0 or the first drum kit, like General MIDI players
+    pub fn preset(&self, bank: u16, program: u16) -> Option<&Preset>{
+        let find = |bank: u16, program: u16| self.presets.iter()
+            .find(|p| p.bank == bank && p.program == program);
+        find(bank, program)
+            .or_else(|| if bank == DRUM_BANK { find(DRUM_BANK, 0) } else { find(0, program) })
+    }
+}
+
+#[derive(Default)]
+pub struct InstrumentBank{
+    instruments: HashMap<String, Instrument>,
+}
+
+impl InstrumentBank{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), String>{
+        if self.instruments.contains_key(name){
+            return Err(format!(
+                "{r}TermDaw: InstrumentBank: there is already an instrument with name {b}\"{n}\"{r} present.",
+                r = RED, b = BLUE, n = name
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn add_sfz(&mut self, name: String, path: &str, sb: &mut SampleBank)
+        -> Result<(), String>
+    {
+        self.check_name(&name)?;
+        let regions = load_sfz(path)?;
+        let mut zones = Vec::new();
+        let mut samples: Vec<String> = Vec::new();
+        let mut rates = HashMap::new();
+        for (file, mut zone) in regions{
+            let sample_name = format!("{}/{}", name, file);
+            let sr = if let Some(sr) = rates.get(&sample_name) { *sr }
+            else {
+                let sr = match sb.add_instrument_sample(sample_name.clone(), &file){
+                    Ok(sr) => sr,
+                    Err(e) => {
+                        // don't leave the samples that did load behind
+                        samples.iter().for_each(|s| sb.mark_dead(s));
+                        return Err(e);
+                    },
+                };
+                samples.push(sample_name.clone());
+                rates.insert(sample_name.clone(), sr);
+                sr
+            };
+            zone.rescale_frames(sr, sb.sample_rate());
+            zone.sample = sample_name;
+            zones.push(zone);
+        }
+        let presets = vec![Preset{ name: name.clone(), bank: 0, program: 0, zones }];
+        self.instruments.insert(name, Instrument{ presets, samples });
+        Ok(())
+    }
+
+    pub fn add_sf2(&mut self, name: String, path: &str, sb: &mut SampleBank)
+        -> Result<(), String>
+    {
+        self.check_name(&name)?;
+        let sf2 = load_sf2(path)?;
+        // only load the samples the presets use
+        let mut samples: Vec<String> = Vec::new();
+        let mut names = HashMap::new();
+        let mut presets = Vec::new();
+        for preset in sf2.presets{
+            let mut zones = Vec::new();
+            for (mut zone, index) in preset.zones{
+                let s = &sf2.samples[index];
+                if s.end <= s.start { continue; }
+                if let Entry::Vacant(entry) = names.entry(index){
+                    let sample_name = format!("{}/{}", name, index);
+                    let data = sf2.data[s.start.min(s.end)..s.end].iter()
+                        .map(|x| *x as f32 / 32768.0).collect::<Vec<_>>();
+                    let res = sb.add_instrument_data(
+                        sample_name.clone(), data.clone(), data, s.sample_rate, 16
+                    );
+                    if let Err(e) = res{
+                        samples.iter().for_each(|s| sb.mark_dead(s));
+                        return Err(e);
+                    }
+                    samples.push(sample_name.clone());
+                    entry.insert(sample_name);
+                }
+                zone.rescale_frames(s.sample_rate, sb.sample_rate());
+                zone.sample = names[&index].clone();
+                zones.push(zone);
+            }
+            presets.push(Preset{
+                name: preset.name,
+                bank: preset.bank,
+                program: preset.program,
+                zones,
+            });
+        }
+        self.instruments.insert(name, Instrument{ presets, samples });
+        Ok(())
+    }
+
+    // marks the samples of the instrument dead in the SampleBank, refresh it afterwards
+    pub fn remove(&mut self, name: &str, sb: &mut SampleBank){
+        if let Some(instrument) = self.instruments.remove(name){
+            instrument.samples.iter().for_each(|s| sb.mark_dead(s));
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Instrument>{
+        self.instruments.get(name)
+    }
+}
@@ -3,11 +3,31 @@ use sdl2::audio::AudioSpecDesired;
 use zen_colour::*;
 
 mod sample;
+mod samplecache;
+mod stream;
+mod clip;
+mod stretch;
 mod graph;
 mod floww;
 mod extensions;
 mod adsr;
 mod synth;
+mod filter;
+mod reverb;
+mod convolution;
+mod delay;
+mod modulation;
+mod distortion;
+mod noise;
+mod fm;
+mod pluck;
+mod drums;
+mod sampler;
+mod instrument;
+mod sfz;
+mod sf2;
+mod granular;
+mod slice;
 mod config;
 mod state;
 mod bufferbank;
@@ -22,6 +42,7 @@ use {
     config::*,
     state::*,
     bufferbank::*,
+    instrument::*,
     ui_workflow::*,
     stream_workflow::*,
 };
@@ -71,10 +92,12 @@ fn main(){
     let proj_sr = config.settings.project_samplerate();
     let buffer_len = config.settings.buffer_length();
     let workflow = config.settings.workflow();
+    let mut sb = SampleBank::new(proj_sr);
+    sb.set_cache_dir(config.settings.sample_cache().map(|dir| wpath.join(dir)));
 
     let mut state = State{
         lua: Lua::new(),
-        sb: SampleBank::new(proj_sr),
+        sb,
         g: Graph::new(config.settings.buffer_length(), proj_sr),
         #[cfg(feature = "lv2")]
         host: Lv2Host::new(1000, buffer_len * 2, proj_sr), // acount for l/r
@@ -82,16 +105,19 @@ fn main(){
         host: (),
         fb: FlowwBank::new(proj_sr, buffer_len),
         bb: BufferBank::new(),
+        ib: InstrumentBank::new(),
         contents,
         config,
         loaded: false,
         cs: 0,
         render_sr: 48000,
         bd: 16,
+        bpm: 120.0,
         output_vertex: String::new(),
         output_file: String::from("outp.wav"),
         cur_samples: Vec::new(),
         cur_resources: Vec::new(),
+        cur_instruments: Vec::new(),
         cur_lv2plugins: Vec::new(),
         cur_lv2params: Vec::new(),
         wdir,
@@ -0,0 +1,176 @@
+use crate::delay::DelayLine;
+
+use std::f32::consts::PI;
+
+// Sine lfo, the phase is derived from the transport time so jumping around in time gives the
+// same modulation as playing from the start.
+pub struct Lfo{
+    rate_hz: f32,
+    phase: f32,
+    inc: f32,
+}
+
+impl Lfo{
+    pub fn new(rate_hz: f32, sr: usize) -> Self{
+        let rate_hz = rate_hz.max(0.0);
+        Self{
+            rate_hz,
+            phase: 0.0,
+            inc: rate_hz / sr as f32,
+        }
+    }
+
+    pub fn set_time(&mut self, t: usize, sr: usize){
+        self.phase = (t as f64 * self.rate_hz as f64 / sr as f64).fract() as f32;
+    }
+
+    // offset in cycles, returns a value in [0, 1]
+    #[inline]
+    pub fn unipolar(&self, offset: f32) -> f32{
+        0.5 + 0.5 * ((self.phase + offset) * 2.0 * PI).sin()
+    }
+
+    #[inline]
+    pub fn advance(&mut self){
+        self.phase = (self.phase + self.inc).fract();
+    }
+}
+
+// Delay lines with a delay time swept by the lfo: chorus and flanger
+pub struct ModDelay{
+    lines: [DelayLine; 2],
+    lfo: Lfo,
+    voices: usize,
+    base: f32,
+    depth: f32,
+    feedback: f32,
+    stereo_phase: f32,
+    fb: [f32; 2],
+    sr: usize,
+}
+
+impl ModDelay{
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        voices: usize, base_ms: f32, rate_hz: f32, depth_ms: f32, feedback: f32,
+        stereo_phase_deg: f32, sr: usize
+    ) -> Self{
+        let frames = |ms: f32| ms.max(0.0) * 0.001 * sr as f32;
+        let base = frames(base_ms).max(1.0);
+        let depth = frames(depth_ms);
+        let max = (base + depth).ceil() as usize + 2;
+        Self{
+            lines: [DelayLine::new(max), DelayLine::new(max)],
+            lfo: Lfo::new(rate_hz, sr),
+            voices: voices.max(1),
+            base,
+            depth,
+            feedback: feedback.clamp(-0.95, 0.95),
+            stereo_phase: stereo_phase_deg / 360.0,
+            fb: [0.0; 2],
+            sr,
+        }
+    }
+
+    // three voices spread around the lfo cycle swept around a longer delay
+    pub fn chorus(
+        rate_hz: f32, depth_ms: f32, feedback: f32, stereo_phase_deg: f32, sr: usize
+    ) -> Self{
+        Self::new(3, 12.0, rate_hz, depth_ms, feedback, stereo_phase_deg, sr)
+    }
+
+    // a single voice swept around a very short delay
+    pub fn flanger(
+        rate_hz: f32, depth_ms: f32, feedback: f32, stereo_phase_deg: f32, sr: usize
+    ) -> Self{
+        Self::new(1, 0.5, rate_hz, depth_ms, feedback, stereo_phase_deg, sr)
+    }
+
+    pub fn set_time(&mut self, t: usize){
+        self.lines.iter_mut().for_each(|line| line.clear());
+        self.fb = [0.0; 2];
+        self.lfo.set_time(t, self.sr);
+    }
+
+    // returns the input mixed half and half with the modulated signal
+    #[inline]
+    pub fn process(&mut self, l: f32, r: f32) -> (f32, f32){
+        let input = [l, r];
+        let mut out = [0.0; 2];
+        for c in 0..2{
+            let mut acc = 0.0;
+            for v in 0..self.voices{
+                let offset = v as f32 / self.voices as f32 + c as f32 * self.stereo_phase;
+                acc += self.lines[c].read(self.base + self.depth * self.lfo.unipolar(offset));
+            }
+            acc /= self.voices as f32;
+            self.lines[c].write(input[c] + self.fb[c] * self.feedback);
+            self.fb[c] = acc;
+            out[c] = (input[c] + acc) * 0.5;
+        }
+        self.lfo.advance();
+        (out[0], out[1])
+    }
+}
+
+pub const MAX_PHASER_STAGES: usize = 12;
+
+// Chain of first order allpasses with a break frequency swept by the lfo
+pub struct Phaser{
+    lfo: Lfo,
+    stages: usize,
+    depth: f32,
+    feedback: f32,
+    stereo_phase: f32,
+    state: [[(f32, f32); MAX_PHASER_STAGES]; 2],
+    fb: [f32; 2],
+    sr: usize,
+}
+
+impl Phaser{
+    // depth goes from zero to one and sweeps up to six octaves above 200hz
+    pub fn new(
+        rate_hz: f32, depth: f32, feedback: f32, stereo_phase_deg: f32, stages: usize, sr: usize
+    ) -> Self{
+        Self{
+            lfo: Lfo::new(rate_hz, sr),
+            stages: stages.clamp(1, MAX_PHASER_STAGES),
+            depth: depth.clamp(0.0, 1.0),
+            feedback: feedback.clamp(-0.95, 0.95),
+            stereo_phase: stereo_phase_deg / 360.0,
+            state: [[(0.0, 0.0); MAX_PHASER_STAGES]; 2],
+            fb: [0.0; 2],
+            sr,
+        }
+    }
+
+    pub fn set_time(&mut self, t: usize){
+        self.state = [[(0.0, 0.0); MAX_PHASER_STAGES]; 2];
+        self.fb = [0.0; 2];
+        self.lfo.set_time(t, self.sr);
+    }
+
+    // returns the input mixed half and half with the phase shifted signal
+    #[inline]
+    pub fn process(&mut self, l: f32, r: f32) -> (f32, f32){
+        let input = [l, r];
+        let mut out = [0.0; 2];
+        for c in 0..2{
+            let sweep = self.lfo.unipolar(c as f32 * self.stereo_phase);
+            let hz = (200.0 * 2.0f32.powf(6.0 * self.depth * sweep)).min(self.sr as f32 * 0.49);
+            let tan = (PI * hz / self.sr as f32).tan();
+            let a = (tan - 1.0) / (tan + 1.0);
+            let mut x = input[c] + self.fb[c] * self.feedback;
+            for (x1, y1) in self.state[c].iter_mut().take(self.stages){
+                let y = a * x + *x1 - a * *y1;
+                *x1 = x;
+                *y1 = y;
+                x = y;
+            }
+            self.fb[c] = x;
+            out[c] = (input[c] + x) * 0.5;
+        }
+        self.lfo.advance();
+        (out[0], out[1])
+    }
+}
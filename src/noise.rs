@@ -0,0 +1,95 @@
+// Small seeded prng (xorshift64*) so renders come out the same every run
+#[derive(Clone, Copy, Debug)]
+pub struct Rng{
+    state: u64,
+}
+
+impl Rng{
+    pub fn new(seed: u64) -> Self{
+        // splitmix the seed so small seeds still give well mixed states, zero is not allowed
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+        Self{ state: if z == 0 { 0x2545F4914F6CDD1D } else { z } }
+    }
+
+    #[inline]
+    pub fn next_u64(&mut self) -> u64{
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    // uniform in [0, 1)
+    #[inline]
+    pub fn next_f32(&mut self) -> f32{
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // uniform in [-1, 1)
+    #[inline]
+    pub fn next_bipolar(&mut self) -> f32{
+        self.next_f32() * 2.0 - 1.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NoiseColor{ White, Pink, Brown }
+
+impl NoiseColor{
+    pub fn from(string: &str) -> Self{
+        match string{
+            "pink" => NoiseColor::Pink,
+            "brown" | "brownian" | "red" => NoiseColor::Brown,
+            _ => NoiseColor::White,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Noise{
+    rng: Rng,
+    color: NoiseColor,
+    pink: [f32; 7],
+    brown: f32,
+}
+
+impl Noise{
+    pub fn new(color: NoiseColor, seed: u64) -> Self{
+        Self{
+            rng: Rng::new(seed),
+            color,
+            pink: [0.0; 7],
+            brown: 0.0,
+        }
+    }
+
+    // all colors are scaled to roughly peak at one
+    #[inline]
+    pub fn next(&mut self) -> f32{
+        let white = self.rng.next_bipolar();
+        match self.color{
+            NoiseColor::White => white,
+            // Paul Kellet's refined pink noise filter
+            NoiseColor::Pink => {
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                pink * 0.2
+            },
+            // leaky integrated white noise
+            NoiseColor::Brown => {
+                self.brown = (self.brown + white * 0.02) * 0.998;
+                (self.brown * 3.5).clamp(-1.0, 1.0)
+            },
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{
+    delay::DelayLine,
+    noise::Rng,
+};
+
+// How the string behaves, shared by all notes of a vertex.
+// decay_sec: time for a held note to fall 60db
+// damping: 0 to 1, how much the high harmonics die faster than the low ones
+// brightness: 0 to 1, lowpass on the noise burst that excites the string
+// pick_position: 0 to 1, where along the string it is plucked, near 0 is thin and bright
+// spread: 0 to 1, the right channel is a slightly detuned string with its own burst
+// release_sec: time for a released note to fall 60db
+#[derive(Clone, Copy)]
+pub struct PluckConf{
+    pub decay_sec: f32,
+    pub damping: f32,
+    pub brightness: f32,
+    pub pick_position: f32,
+    pub spread: f32,
+    pub release_sec: f32,
+}
+
+struct PluckString{
+    line: DelayLine,
+    period: f32,
+    prev: f32,
+    sustain_gain: f32,
+    release_gain: f32,
+}
+
+impl PluckString{
+    fn new(conf: &PluckConf, hz: f32, vel: f32, rng: &mut Rng, sr: usize) -> Self{
+        let hz = hz.clamp(20.0, sr as f32 * 0.25);
+        // the two point average in the loop adds half a frame of delay
+        let period = (sr as f32 / hz - 0.5).max(2.0);
+        let len = period.round().max(2.0) as usize;
+        let mut line = DelayLine::new(period.ceil() as usize + 1);
+        // lowpassed noise burst, then a comb at the pick position
+        let mut burst = Vec::with_capacity(len);
+        let gamma = conf.brightness.clamp(0.01, 1.0);
+        let mut lp = 0.0;
+        for _ in 0..len{
+            lp += gamma * (rng.next_bipolar() - lp);
+            burst.push(lp);
+        }
+        let pick = (conf.pick_position.clamp(0.0, 1.0) * len as f32).round() as usize;
+        if pick > 0 && pick < len{
+            for i in (pick..len).rev(){
+                burst[i] -= burst[i - pick];
+            }
+        }
+        let mean = burst.iter().sum::<f32>() / len as f32;
+        let max = burst.iter().fold(0.0f32, |max, x| max.max((x - mean).abs()));
+        let scale = if max > 0.0 { vel / max } else { 0.0 };
+        for x in burst{
+            line.write((x - mean) * scale);
+        }
+        // loop gain per pass through the string to fall 60db in the given time
+        let gain = |sec: f32| 0.001f32.powf(1.0 / (sec.max(0.001) * hz));
+        Self{
+            line,
+            period,
+            prev: 0.0,
+            sustain_gain: gain(conf.decay_sec),
+            release_gain: gain(conf.release_sec),
+        }
+    }
+
+    #[inline]
+    fn next(&mut self, damping: f32, released: bool) -> f32{
+        let y = self.line.read(self.period);
+        // blend between no filtering and the classic two point average
+        let filtered = y * (1.0 - damping * 0.5) + self.prev * damping * 0.5;
+        self.prev = y;
+        let gain = if released { self.release_gain } else { self.sustain_gain };
+        self.line.write(filtered * gain);
+        y
+    }
+}
+
+pub struct PluckVoice{
+    strings: [PluckString; 2],
+}
+
+impl PluckVoice{
+    pub fn new(conf: &PluckConf, hz: f32, vel: f32, seed: u64, sr: usize) -> Self{
+        let mut rng = Rng::new(seed);
+        let spread = conf.spread.clamp(0.0, 1.0);
+        let left = PluckString::new(conf, hz, vel, &mut rng, sr);
+        let right = if spread > 0.0{
+            // up to ten cents apart and a burst of its own
+            let detuned = hz * 2.0f32.powf(spread * 10.0 / 1200.0);
+            PluckString::new(conf, detuned, vel, &mut rng, sr)
+        } else {
+            PluckString::new(conf, hz, vel, &mut Rng::new(seed), sr)
+        };
+        Self{ strings: [left, right] }
+    }
+
+    #[inline]
+    pub fn next(&mut self, conf: &PluckConf, released: bool) -> (f32, f32){
+        let damping = conf.damping.clamp(0.0, 1.0);
+        let l = self.strings[0].next(damping, released);
+        let r = self.strings[1].next(damping, released);
+        // spread zero is two identical strings
+        let spread = conf.spread.clamp(0.0, 1.0);
+        let mid = (l + r) * 0.5;
+        (mid + (l - mid) * spread, mid + (r - mid) * spread)
+    }
+}
@@ -0,0 +1,127 @@
+// Freeverb style reverb: eight parallel lowpass feedback combs into four series allpasses per
+// channel, the right channel uses slightly longer delay lines to decorrelate it from the left.
+// Based on the public domain Freeverb by Jezar at Dreampoint.
+
+// tunings are in frames at 44100hz and get scaled to the project samplerate
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+const FIXED_GAIN: f32 = 0.015;
+const SCALE_WET: f32 = 3.0;
+const SCALE_DAMP: f32 = 0.4;
+const SCALE_ROOM: f32 = 0.28;
+const OFFSET_ROOM: f32 = 0.7;
+
+struct Comb{
+    buf: Vec<f32>,
+    pos: usize,
+    store: f32,
+}
+
+impl Comb{
+    fn new(len: usize) -> Self{
+        Self{ buf: vec![0.0; len.max(1)], pos: 0, store: 0.0 }
+    }
+
+    #[inline]
+    fn process(&mut self, x: f32, feedback: f32, damp: f32) -> f32{
+        let y = self.buf[self.pos];
+        self.store = y * (1.0 - damp) + self.store * damp;
+        self.buf[self.pos] = x + self.store * feedback;
+        self.pos = (self.pos + 1) % self.buf.len();
+        y
+    }
+
+    fn clear(&mut self){
+        self.buf.iter_mut().for_each(|s| *s = 0.0);
+        self.store = 0.0;
+    }
+}
+
+struct AllPass{
+    buf: Vec<f32>,
+    pos: usize,
+}
+
+impl AllPass{
+    fn new(len: usize) -> Self{
+        Self{ buf: vec![0.0; len.max(1)], pos: 0 }
+    }
+
+    #[inline]
+    fn process(&mut self, x: f32) -> f32{
+        let b = self.buf[self.pos];
+        self.buf[self.pos] = x + b * 0.5;
+        self.pos = (self.pos + 1) % self.buf.len();
+        b - x
+    }
+
+    fn clear(&mut self){
+        self.buf.iter_mut().for_each(|s| *s = 0.0);
+    }
+}
+
+pub struct Reverb{
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<AllPass>; 2],
+    pre_delay: [Vec<f32>; 2],
+    pre_pos: usize,
+    feedback: f32,
+    damp: f32,
+    width: f32,
+}
+
+impl Reverb{
+    // room_size, damping and width go from zero to one
+    pub fn new(room_size: f32, damping: f32, pre_delay_ms: f32, width: f32, sr: usize) -> Self{
+        let scale = |frames: usize| (frames as f32 * sr as f32 / 44100.0) as usize;
+        let combs = |spread| COMB_TUNINGS.iter().map(|t| Comb::new(scale(t + spread))).collect();
+        let allpasses = |spread| ALLPASS_TUNINGS.iter()
+            .map(|t| AllPass::new(scale(t + spread))).collect();
+        let pre_len = (pre_delay_ms.max(0.0) * 0.001 * sr as f32) as usize + 1;
+        Self{
+            combs: [combs(0), combs(STEREO_SPREAD)],
+            allpasses: [allpasses(0), allpasses(STEREO_SPREAD)],
+            pre_delay: [vec![0.0; pre_len], vec![0.0; pre_len]],
+            pre_pos: 0,
+            feedback: room_size.clamp(0.0, 1.0) * SCALE_ROOM + OFFSET_ROOM,
+            damp: damping.clamp(0.0, 1.0) * SCALE_DAMP,
+            width: width.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn clear(&mut self){
+        for c in 0..2{
+            self.combs[c].iter_mut().for_each(|comb| comb.clear());
+            self.allpasses[c].iter_mut().for_each(|ap| ap.clear());
+            self.pre_delay[c].iter_mut().for_each(|s| *s = 0.0);
+        }
+        self.pre_pos = 0;
+    }
+
+    // returns the fully wet signal
+    #[inline]
+    pub fn process(&mut self, l: f32, r: f32) -> (f32, f32){
+        let pre_len = self.pre_delay[0].len();
+        let input = [
+            std::mem::replace(&mut self.pre_delay[0][self.pre_pos], l),
+            std::mem::replace(&mut self.pre_delay[1][self.pre_pos], r),
+        ];
+        self.pre_pos = (self.pre_pos + 1) % pre_len;
+        let x = (input[0] + input[1]) * FIXED_GAIN;
+        let mut out = [0.0; 2];
+        for (c, o) in out.iter_mut().enumerate(){
+            let mut acc = 0.0;
+            for comb in self.combs[c].iter_mut(){
+                acc += comb.process(x, self.feedback, self.damp);
+            }
+            for ap in self.allpasses[c].iter_mut(){
+                acc = ap.process(acc);
+            }
+            *o = acc * SCALE_WET;
+        }
+        let wet1 = self.width * 0.5 + 0.5;
+        let wet2 = (1.0 - self.width) * 0.5;
+        (out[0] * wet1 + out[1] * wet2, out[1] * wet1 + out[0] * wet2)
+    }
+}
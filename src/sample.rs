@@ -1,9 +1,28 @@
-use std::collections::{ HashMap, HashSet };
+use std::{
+    collections::{ HashMap, HashSet },
+    path::{ Path, PathBuf },
+};
 
 use rubato::{
     Resampler, SincFixedIn, SincInterpolationType, SincInterpolationParameters, WindowFunction
 };
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{ DecoderOptions, CODEC_TYPE_NULL },
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
 use zen_colour::*;
+use mlua::prelude::*;
+
+use crate::{
+    samplecache::{ SampleCache, CachedSample },
+    stream::StreamSource,
+    stretch::time_stretch,
+};
 
 fn absmax(samples: &[f32]) -> f32{
     absmaxlen(samples, std::usize::MAX)
@@ -13,6 +32,49 @@ fn absmaxlen(samples: &[f32], len: usize) -> f32{
     samples.iter().take(len).fold(0.0, |max, s| { let a = s.abs(); if a > max { a } else { max } })
 }
 
+fn deinterleave(linear: Vec<f32>, channels: usize) -> Vec<Vec<f32>>{
+    let mut chs = vec![Vec::with_capacity(linear.len() / channels + 1); channels];
+    for (i, s) in linear.into_iter().enumerate(){
+        chs[i % channels].push(s);
+    }
+    chs
+}
+
+// (left, right) gain of every channel when downmixing to stereo, ITU style: centre and
+// surrounds at -3dB, lfe left out. Channel orders are the wav ones.
+fn downmix_gains(channels: usize) -> Vec<(f32, f32)>{
+    let h = std::f32::consts::FRAC_1_SQRT_2;
+    let (fl, fr, c, lfe) = ((1.0, 0.0), (0.0, 1.0), (h, h), (0.0, 0.0));
+    let (sl, sr) = ((h, 0.0), (0.0, h));
+    match channels{
+        1 => vec![(1.0, 1.0)],
+        2 => vec![fl, fr],
+        3 => vec![fl, fr, c],
+        4 => vec![fl, fr, sl, sr],
+        5 => vec![fl, fr, c, sl, sr],
+        6 => vec![fl, fr, c, lfe, sl, sr],
+        // 6.1 has a back centre
+        7 => vec![fl, fr, c, lfe, (h * h, h * h), sl, sr],
+        8 => vec![fl, fr, c, lfe, sl, sr, sl, sr],
+        // no standard layout, alternate left and right
+        n => (0..n).map(|i| if i % 2 == 0 { (h, 0.0) } else { (0.0, h) }).collect(),
+    }
+}
+
+fn downmix(chs: Vec<Vec<f32>>) -> Vec<Vec<f32>>{
+    let len = chs.iter().map(|c| c.len()).min().unwrap_or(0);
+    let gains = downmix_gains(chs.len());
+    let mut l = vec![0.0; len];
+    let mut r = vec![0.0; len];
+    for (ch, (gl, gr)) in chs.iter().zip(gains){
+        for i in 0..len{
+            l[i] += ch[i] * gl;
+            r[i] += ch[i] * gr;
+        }
+    }
+    vec![l, r]
+}
+
 fn mean_energy(samples: &[f32]) -> f32{
     if samples.is_empty() {
         0.0
@@ -136,11 +198,12 @@ impl Sample{
         self.r.iter_mut().for_each(|sample| *sample *= scaler);
     }
 
-    pub fn mix_down(&mut self){
+    // without normalizing the mix is the average of both channels
+    pub fn mix_down(&mut self, normalize: bool){
         let l = std::mem::take(&mut self.l);
         let r = std::mem::take(&mut self.r);
         let mut mix = l.into_iter().zip(r).map(|(l,r)| l + r).collect::<Vec<_>>();
-        let scale = 1.0 / absmax(&mix);
+        let scale = if normalize { 1.0 / absmax(&mix) } else { 0.5 };
         mix.iter_mut().for_each(|s| *s *= scale);
         self.l.clone_from(&mix);
         self.r = mix;
@@ -174,6 +237,36 @@ impl Sample{
         })
     }
 
+    // keep the frames from start to end
+    pub fn trim(&mut self, start: usize, end: usize){
+        let end = end.min(self.len());
+        let start = start.min(end);
+        self.l.truncate(end);
+        self.r.truncate(end);
+        self.l.drain(..start);
+        self.r.drain(..start);
+    }
+
+    pub fn reverse(&mut self){
+        self.l.reverse();
+        self.r.reverse();
+    }
+
+    // linear fades over the given amount of frames
+    pub fn fade(&mut self, fade_in: usize, fade_out: usize){
+        let len = self.len();
+        for i in 0..fade_in.min(len){
+            let amp = i as f32 / fade_in as f32;
+            self.l[i] *= amp;
+            self.r[i] *= amp;
+        }
+        for i in 0..fade_out.min(len){
+            let amp = i as f32 / fade_out as f32;
+            self.l[len - 1 - i] *= amp;
+            self.r[len - 1 - i] *= amp;
+        }
+    }
+
     pub fn interleave(self) -> Vec<f32>{
         let mut res = Vec::new();
         for i in 0..self.len(){
@@ -187,14 +280,26 @@ impl Sample{
 pub struct SampleBank{
     sample_rate: usize,
     samples: Vec<Sample>,
+    // loop points in frames per sample
+    loops: Vec<Option<(usize, usize)>>,
     names: HashMap<String, usize>,
     max_sr: usize,
     max_bd: usize,
     marked: HashSet<usize>,
+    cache: Option<SampleCache>,
+    // samples read from disk while playing
+    streams: HashMap<String, StreamSource>,
 }
 
-#[derive(Clone,Copy,PartialEq,Eq)]
-pub enum SampleLoadMethod{ Stereo, Left, Right, Loudest, Norm, Mix }
+// a sample ready to be added to the bank
+pub enum Prepared{
+    Memory(CachedSample),
+    // with the sample rate and bit depth of the file
+    Stream(StreamSource, usize, usize),
+}
+
+#[derive(Clone,Copy,PartialEq,Eq,Default,Debug)]
+pub enum SampleLoadMethod{ #[default] Stereo, Left, Right, Loudest, Norm, Mix, Downmix }
 
 impl SampleLoadMethod{
     pub fn from(string: &str) -> Self{
@@ -204,61 +309,203 @@ impl SampleLoadMethod{
             "loudest" => SampleLoadMethod::Loudest,
             "normalize-seperate" => SampleLoadMethod::Norm,
             "mix-down" => SampleLoadMethod::Mix,
+            "downmix" => SampleLoadMethod::Downmix,
             _ => SampleLoadMethod::Stereo,
         }
     }
 }
 
+// What load_sample does with a sample. Times are in seconds, start and end are taken from the
+// file, the loop points are relative to the start. Without normalizing the sample keeps the
+// level it has in the file, integer samples are scaled by their bit depth.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SampleLoadOptions{
+    pub method: SampleLoadMethod,
+    // channels to load, counting from 1: one for mono or two for left and right
+    pub channels: Option<Vec<usize>>,
+    pub normalize: bool,
+    pub start: f32,
+    pub end: Option<f32>,
+    pub reverse: bool,
+    pub fade_in: f32,
+    pub fade_out: f32,
+    pub gain_db: f32,
+    pub loop_start: Option<f32>,
+    pub loop_end: Option<f32>,
+    // read from disk while playing instead of loading it
+    pub stream: bool,
+    pub stretch: f32,
+    pub pitch_semitones: f32,
+    // stretch to this many beats at the project tempo instead
+    pub fit_to_beats: Option<f32>,
+    // the project tempo, not an option but filled in for fit_to_beats
+    pub bpm: f32,
+}
+
+impl Default for SampleLoadOptions{
+    fn default() -> Self{
+        Self{
+            method: SampleLoadMethod::Stereo,
+            channels: None,
+            normalize: true,
+            start: 0.0,
+            end: None,
+            reverse: false,
+            fade_in: 0.0,
+            fade_out: 0.0,
+            gain_db: 0.0,
+            loop_start: None,
+            loop_end: None,
+            stream: false,
+            stretch: 1.0,
+            pitch_semitones: 0.0,
+            fit_to_beats: None,
+            bpm: 0.0,
+        }
+    }
+}
+
+impl SampleLoadOptions{
+    const KEYS: [&'static str; 15] = [
+        "mode", "channels", "normalize", "start", "ends", "reverse", "fade_in", "fade_out",
+        "gain_db", "loop_start", "loop_end", "stream", "stretch", "pitch_semitones",
+        "fit_to_beats"
+    ];
+
+    // nothing that needs the whole sample in memory: streams can read these from the file itself
+    fn streams_directly(&self) -> bool{
+        use SampleLoadMethod::*;
+        matches!(self.method, Stereo | Left | Right)
+            && self.start <= 0.0 && self.end.is_none() && !self.reverse
+            && self.fade_in <= 0.0 && self.fade_out <= 0.0
+            && self.stretch == 1.0 && self.pitch_semitones == 0.0 && self.fit_to_beats.is_none()
+    }
+
+    // time stretch and pitch shift, in the sample rate of the file
+    fn stretch(&self, sample: &mut Sample, sr: usize) -> Result<(), String>{
+        let stretch = match self.fit_to_beats{
+            Some(beats) if self.bpm > 0.0 => beats * 60.0 / self.bpm / (sample.len() as f32 / sr as f32),
+            _ => self.stretch,
+        };
+        if !(0.01..=100.0).contains(&stretch){
+            return Err(format!(
+                "{r}TermDaw: SampleBank: can't stretch a sample by {b}{s}{r}.",
+                r = RED, b = BLUE, s = stretch
+            ));
+        }
+        // stretch more and resample it back to the length asked for, that shifts the pitch
+        let pitch = 2.0f32.powf(self.pitch_semitones / 12.0);
+        if (stretch * pitch - 1.0).abs() > 0.0001{
+            (sample.l, sample.r) = time_stretch(&sample.l, &sample.r, stretch * pitch);
+        }
+        if (pitch - 1.0).abs() > 0.0001{
+            *sample = sample.resample((sr as f32 * pitch).round() as usize, sr)?;
+        }
+        Ok(())
+    }
+
+    // cut and reverse, in the sample rate of the file
+    fn cut(&self, sample: &mut Sample, sr: usize){
+        let frames = |sec: f32| (sec.max(0.0) * sr as f32) as usize;
+        sample.trim(frames(self.start), self.end.map(frames).unwrap_or(usize::MAX));
+        if self.reverse { sample.reverse(); }
+    }
+
+    // fade and amplify, after normalizing
+    fn shape(&self, sample: &mut Sample, sr: usize){
+        let frames = |sec: f32| (sec.max(0.0) * sr as f32) as usize;
+        sample.fade(frames(self.fade_in), frames(self.fade_out));
+        sample.apply_gain(10.0f32.powf(self.gain_db / 20.0), usize::MAX);
+    }
+
+    // loop points in frames of the project sample rate
+    fn loop_frames(&self, len: usize, sr: usize) -> Option<(usize, usize)>{
+        let frames = |sec: f32| ((sec.max(0.0) * sr as f32) as usize).min(len);
+        let start = frames(self.loop_start.unwrap_or(0.0));
+        let end = frames(self.loop_end?);
+        if end > start { Some((start, end)) } else { None }
+    }
+}
+
+// a mode string or a table of options
+impl<'lua> FromLua<'lua> for SampleLoadOptions{
+    fn from_lua(value: LuaValue<'lua>, _: &'lua Lua) -> LuaResult<Self>{
+        match value{
+            LuaValue::String(mode) => Ok(Self{
+                method: SampleLoadMethod::from(mode.to_str()?),
+                ..Default::default()
+            }),
+            LuaValue::Table(table) => {
+                for key in table.clone().pairs::<String, LuaValue>(){
+                    let (key, _) = key?;
+                    if !Self::KEYS.contains(&key.as_str()){
+                        return Err(LuaError::RuntimeError(format!(
+                            "load_sample: unknown option \"{}\", options are: {}",
+                            key, Self::KEYS.join(", ")
+                        )));
+                    }
+                }
+                let mode: Option<String> = table.get("mode")?;
+                Ok(Self{
+                    method: SampleLoadMethod::from(mode.as_deref().unwrap_or("")),
+                    channels: table.get("channels")?,
+                    normalize: table.get::<_, Option<bool>>("normalize")?.unwrap_or(true),
+                    start: table.get::<_, Option<f32>>("start")?.unwrap_or(0.0),
+                    end: table.get("ends")?,
+                    reverse: table.get::<_, Option<bool>>("reverse")?.unwrap_or(false),
+                    fade_in: table.get::<_, Option<f32>>("fade_in")?.unwrap_or(0.0),
+                    fade_out: table.get::<_, Option<f32>>("fade_out")?.unwrap_or(0.0),
+                    gain_db: table.get::<_, Option<f32>>("gain_db")?.unwrap_or(0.0),
+                    loop_start: table.get("loop_start")?,
+                    loop_end: table.get("loop_end")?,
+                    stream: table.get::<_, Option<bool>>("stream")?.unwrap_or(false),
+                    stretch: table.get::<_, Option<f32>>("stretch")?.unwrap_or(1.0),
+                    pitch_semitones: table.get::<_, Option<f32>>("pitch_semitones")?
+                        .unwrap_or(0.0),
+                    fit_to_beats: table.get("fit_to_beats")?,
+                    bpm: 0.0,
+                })
+            },
+            LuaValue::Nil => Ok(Self::default()),
+            other => Err(LuaError::FromLuaConversionError{
+                from: other.type_name(),
+                to: "SampleLoadOptions",
+                message: Some(String::from("expected a mode string or a table of options")),
+            }),
+        }
+    }
+}
+
 impl SampleBank{
     pub fn new(sample_rate: usize) -> Self{
         Self{
             sample_rate,
             samples: Vec::new(),
+            loops: Vec::new(),
             names: HashMap::new(),
             max_sr: 0,
             max_bd: 0,
             marked: HashSet::new(),
+            cache: None,
+            streams: HashMap::new(),
         }
     }
 
-    pub fn add(&mut self, name: String, file: &str, method: SampleLoadMethod) -> Result<(), String>{
-        if self.names.contains_key(&name) {
+    // Returns the channels, sample rate and bit depth of an audio file, integer samples are
+    // scaled to [-1, 1]. Wav files are read with hound, everything else goes through symphonia.
+    fn decode(file: &str) -> Result<(Vec<Vec<f32>>, usize, usize), String>{
+        if !Path::new(file).is_file(){
             return Err(format!(
-                "{r}TermDaw: SampleBank: there is already a sample with name {b}\"{n}\"{r} present.",
-                r = RED, b = BLUE, n = name
+                "{r}TermDaw: SampleBank: could not open file {b}\"{f}\"{r}.",
+                r = RED, b = BLUE, f = file
             ));
         }
         let mut reader = if let Ok(reader) = hound::WavReader::open(file){
             reader
         } else {
-            return Err(format!(
-                "{r}TermDaw: SampleBank: could not open file {b}\"{f}\"{r}.",
-                r = RED, b = BLUE, f = file
-            ));
+            return Self::decode_symphonia(file);
         };
         let specs = reader.spec();
-        if method == SampleLoadMethod::Stereo && specs.channels != 2{
-            return Err(format!(
-                "{r}TermDaw: SampleBank: only 2 channel samples are supported for stereo samples, found {b}{s}{r} channels.",
-                r = RED, b = BLUE, s = specs.channels
-            ));
-        }
-        if method != SampleLoadMethod::Stereo && specs.channels > 2{
-            return Err(format!(
-                "{r}TermDaw: SampleBank: only 1,2 channel samples are supported for left or right samples, found {b}{s}{r} channels.",
-                r = RED, b = BLUE, s = specs.channels
-            ));
-        }
-        let sr = specs.sample_rate as usize;
-        let bd = specs.bits_per_sample;
-        self.max_sr = self.max_sr.max(sr);
-        self.max_bd = self.max_bd.max(bd as usize);
-        if sr > self.sample_rate {
-            println!(
-                "{y}TermDaw: warning: sample {b}\"{n}\"{y} has a higher samplerate({b}{sr}{y}) than the project({b}{psr}{y}).",
-                y = YELLOW, b = BLUE, n = name, sr = sr, psr = self.sample_rate
-            );
-        }
         let mut linear = Vec::new();
         if specs.sample_format == hound::SampleFormat::Float{
             for s in reader.samples::<f32>(){
@@ -266,54 +513,374 @@ impl SampleBank{
                 linear.push(s.unwrap());
             }
         } else {
-            //let max = ((1 << bd) / 2 - 1) as f32;
+            let max = (1u64 << (specs.bits_per_sample.max(1) - 1)) as f32;
             for s in reader.samples::<i32>(){
                 if s.is_err() { continue; }
-                linear.push(s.unwrap() as f32);
+                linear.push(s.unwrap() as f32 / max);
             }
         }
-        let mut l = Vec::new();
-        let mut r = Vec::new();
-        if specs.channels == 1{
-            if method == SampleLoadMethod::Left{
-                l = linear;
+        let channels = (specs.channels as usize).max(1);
+        Ok((deinterleave(linear, channels), specs.sample_rate as usize,
+            specs.bits_per_sample as usize))
+    }
+
+    // flac, ogg vorbis, mp3, aiff and the wav files hound can't read
+    fn decode_symphonia(file: &str) -> Result<(Vec<Vec<f32>>, usize, usize), String>{
+        let unsupported = |reason: &str| format!(
+            "{r}TermDaw: SampleBank: could not decode {b}\"{f}\"{r}: {x}. Supported are wav, flac, ogg vorbis, mp3 and aiff.",
+            r = RED, b = BLUE, f = file, x = reason
+        );
+        let src = std::fs::File::open(file).map_err(|e| unsupported(&e.to_string()))?;
+        let mss = MediaSourceStream::new(Box::new(src), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = Path::new(file).extension().and_then(|e| e.to_str()){
+            hint.with_extension(ext);
+        }
+        let probed = symphonia::default::get_probe()
+            .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+            .map_err(|_| unsupported("unknown format"))?;
+        let mut format = probed.format;
+        let track = format.tracks().iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| unsupported("no audio track"))?;
+        let track_id = track.id;
+        let params = track.codec_params.clone();
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&params, &DecoderOptions::default())
+            .map_err(|_| unsupported("unsupported codec"))?;
+        let mut sr = params.sample_rate.unwrap_or(0) as usize;
+        let mut channels = params.channels.map(|c| c.count()).unwrap_or(0);
+        let mut linear = Vec::new();
+        let mut buf: Option<SampleBuffer<f32>> = None;
+        loop{
+            let packet = match format.next_packet(){
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(SymphoniaError::ResetRequired) => break,
+                Err(e) => return Err(unsupported(&e.to_string())),
+            };
+            if packet.track_id() != track_id { continue; }
+            let decoded = match decoder.decode(&packet){
+                Ok(decoded) => decoded,
+                // a broken packet, skip it like hound skips broken samples
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(e) => return Err(unsupported(&e.to_string())),
+            };
+            let spec = *decoded.spec();
+            sr = spec.rate as usize;
+            channels = spec.channels.count();
+            if buf.as_ref().is_none_or(|b| b.capacity() < decoded.capacity() * channels){
+                buf = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+            }
+            if let Some(buf) = buf.as_mut(){
+                buf.copy_interleaved_ref(decoded);
+                linear.extend_from_slice(buf.samples());
+            }
+        }
+        if linear.is_empty() || channels == 0 || sr == 0{
+            return Err(unsupported("no audio found"));
+        }
+        // lossy formats have no bit depth
+        let bd = params.bits_per_sample.unwrap_or(16) as usize;
+        Ok((deinterleave(linear, channels), sr, bd))
+    }
+
+    fn check_sr_bd(&mut self, name: &str, sr: usize, bd: usize){
+        self.max_sr = self.max_sr.max(sr);
+        self.max_bd = self.max_bd.max(bd);
+        if sr > self.sample_rate {
+            println!(
+                "{y}TermDaw: warning: sample {b}\"{n}\"{y} has a higher samplerate({b}{sr}{y}) than the project({b}{psr}{y}).",
+                y = YELLOW, b = BLUE, n = name, sr = sr, psr = self.sample_rate
+            );
+        }
+    }
+
+    fn insert(&mut self, name: String, sample: Sample, loop_frames: Option<(usize, usize)>){
+        self.samples.push(sample);
+        self.loops.push(loop_frames);
+        self.names.insert(name, self.samples.len() - 1);
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), String>{
+        if self.names.contains_key(name) || self.streams.contains_key(name) {
+            return Err(format!(
+                "{r}TermDaw: SampleBank: there is already a sample with name {b}\"{n}\"{r} present.",
+                r = RED, b = BLUE, n = name
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn set_cache_dir(&mut self, dir: Option<PathBuf>){
+        self.cache = dir.map(SampleCache::new);
+    }
+
+    // Decodes and processes a sample, or takes it from the cache. Only reads the bank so samples
+    // can be prepared in parallel, add them with add_prepared.
+    pub fn prepare(&self, name: &str, file: &str, options: &SampleLoadOptions)
+        -> Result<Prepared, String>
+    {
+        self.check_name(name)?;
+        if options.stream{
+            return self.prepare_stream(file, options);
+        }
+        let key = format!("{:?}", options);
+        let sr = self.sample_rate;
+        if let Some(cached) = self.cache.as_ref().and_then(|c| c.get(file, &key, sr)){
+            println!("{s}Status: sample {b}\"{x}\"{s} comes from the cache.",
+                s = DEFAULT, b = BLUE, x = name);
+            return Ok(Prepared::Memory(cached));
+        }
+        let loaded = Self::load(file, options, sr)?;
+        if let Some(Err(e)) = self.cache.as_ref().map(|c| c.put(file, &key, sr, &loaded)){
+            println!("{y}TermDaw: warning: could not cache sample {b}\"{n}\"{y}: {e}",
+                y = YELLOW, b = BLUE, n = name, e = e);
+        }
+        Ok(Prepared::Memory(loaded))
+    }
+
+    // Wav files at the project sample rate are streamed as they are. Everything else is
+    // processed like any sample once and written to a float wav in the cache to stream from.
+    fn prepare_stream(&self, file: &str, options: &SampleLoadOptions)
+        -> Result<Prepared, String>
+    {
+        let sr = self.sample_rate;
+        if let Some(prepared) = Self::direct_stream(file, options, sr){
+            return Ok(prepared);
+        }
+        let dir = self.cache.as_ref().map(|c| c.dir().to_path_buf())
+            .unwrap_or_else(|| std::env::temp_dir().join("termdaw-streams"));
+        let key = format!("{:?}", options);
+        let path = SampleCache::stream_file(&dir, file, &key, sr).ok_or_else(|| format!(
+            "{r}TermDaw: SampleBank: could not open file {b}\"{f}\"{r}.",
+            r = RED, b = BLUE, f = file
+        ))?;
+        // converted before in this or an earlier session
+        if let Ok(reader) = hound::WavReader::open(&path){
+            let len = reader.duration() as usize;
+            let (file_sr, bd) = Self::decode_spec(file).unwrap_or((sr, 32));
+            let source = StreamSource{
+                path, len, picks: (0, 1), gain: 1.0,
+                loop_frames: options.loop_frames(len, sr),
+            };
+            return Ok(Prepared::Stream(source, file_sr, bd));
+        }
+        let loaded = Self::load(file, options, sr)?;
+        let write = || -> Result<(), hound::Error>{
+            std::fs::create_dir_all(&dir)?;
+            let spec = hound::WavSpec{
+                channels: 2,
+                sample_rate: sr as u32,
+                bits_per_sample: 32,
+                sample_format: hound::SampleFormat::Float,
+            };
+            let tmp = path.with_extension("tmp");
+            let mut writer = hound::WavWriter::create(&tmp, spec)?;
+            for (l, r) in loaded.sample.l.iter().zip(&loaded.sample.r){
+                writer.write_sample(*l)?;
+                writer.write_sample(*r)?;
+            }
+            writer.finalize()?;
+            std::fs::rename(&tmp, &path)?;
+            SampleCache::remove_old_streams(&path);
+            Ok(())
+        };
+        write().map_err(|e| format!(
+            "{r}TermDaw: SampleBank: could not write the stream of {b}\"{f}\"{r}: {e}",
+            r = RED, b = BLUE, f = file, e = e
+        ))?;
+        let source = StreamSource{
+            path, len: loaded.sample.len(), picks: (0, 1), gain: 1.0,
+            loop_frames: loaded.loop_frames,
+        };
+        Ok(Prepared::Stream(source, loaded.sr, loaded.bd))
+    }
+
+    // sample rate and bit depth of a wav file
+    fn decode_spec(file: &str) -> Option<(usize, usize)>{
+        let spec = hound::WavReader::open(file).ok()?.spec();
+        Some((spec.sample_rate as usize, spec.bits_per_sample as usize))
+    }
+
+    // None when the file needs converting first
+    fn direct_stream(file: &str, options: &SampleLoadOptions, sr: usize) -> Option<Prepared>{
+        if !options.streams_directly() { return None; }
+        let mut reader = hound::WavReader::open(file).ok()?;
+        let spec = reader.spec();
+        let channels = spec.channels as usize;
+        if spec.sample_rate as usize != sr { return None; }
+        let picks = match (&options.channels, options.method){
+            (Some(picks), _) => {
+                let first = *picks.first()?;
+                let last = *picks.last()?;
+                if picks.len() > 2 || first == 0 || last == 0 || first.max(last) > channels{
+                    return None;
+                }
+                (first - 1, last - 1)
+            },
+            (None, SampleLoadMethod::Stereo) if channels == 2 => (0, 1),
+            (None, SampleLoadMethod::Left) if channels <= 2 => (0, 0),
+            (None, SampleLoadMethod::Right) if channels <= 2 => (channels - 1, channels - 1),
+            _ => return None,
+        };
+        let mut gain = 10.0f32.powf(options.gain_db / 20.0);
+        if options.normalize{
+            // one pass over the file for the peak, without keeping it
+            let max = (1u64 << (spec.bits_per_sample.max(1) - 1)) as f32;
+            let mut peak = 0.0f32;
+            let mut scan = |i: usize, x: f32| {
+                let ch = i % channels;
+                if ch == picks.0 || ch == picks.1 { peak = peak.max(x.abs()); }
+            };
+            if spec.sample_format == hound::SampleFormat::Float{
+                reader.samples::<f32>().enumerate().for_each(|(i, x)| scan(i, x.unwrap_or(0.0)));
             } else {
-                r = linear;
+                reader.samples::<i32>().enumerate()
+                    .for_each(|(i, x)| scan(i, x.unwrap_or(0) as f32 / max));
             }
-        } else {
-            let half_len = linear.len() /  2;
-            for i in 0..half_len{
-                l.push(linear[i * 2]);
-                r.push(linear[i * 2 + 1]);
+            if peak > 0.0 { gain /= peak; }
+        }
+        let len = reader.duration() as usize;
+        let source = StreamSource{
+            path: PathBuf::from(file), len, picks, gain,
+            loop_frames: options.loop_frames(len, sr),
+        };
+        Some(Prepared::Stream(source, spec.sample_rate as usize, spec.bits_per_sample as usize))
+    }
+
+    pub fn add_prepared(&mut self, name: String, prepared: Prepared) -> Result<(), String>{
+        self.check_name(&name)?;
+        match prepared{
+            Prepared::Memory(loaded) => {
+                self.check_sr_bd(&name, loaded.sr, loaded.bd);
+                self.insert(name, loaded.sample, loaded.loop_frames);
+            },
+            Prepared::Stream(source, sr, bd) => {
+                self.check_sr_bd(&name, sr, bd);
+                self.streams.insert(name, source);
+            },
+        }
+        Ok(())
+    }
+
+    // decode and process a sample for the project sample rate
+    fn load(file: &str, options: &SampleLoadOptions, project_sr: usize)
+        -> Result<CachedSample, String>
+    {
+        let method = options.method;
+        let (mut chs, sr, bd) = Self::decode(file)?;
+        if let Some(picks) = &options.channels{
+            if picks.is_empty() || picks.len() > 2{
+                return Err(format!(
+                    "{r}TermDaw: SampleBank: pick {b}1{r} or {b}2{r} channels, not {b}{p}{r}.",
+                    r = RED, b = BLUE, p = picks.len()
+                ));
             }
-            if linear.len() > half_len * 2{
-                l.push(linear[linear.len() - 1]);
+            if let Some(bad) = picks.iter().find(|p| **p == 0 || **p > chs.len()){
+                return Err(format!(
+                    "{r}TermDaw: SampleBank: there is no channel {b}{c}{r} in {b}\"{f}\"{r}, it has {b}{n}{r} channels.",
+                    r = RED, b = BLUE, c = bad, f = file, n = chs.len()
+                ));
             }
+            // one picked channel plays on both sides
+            chs = [picks[0], *picks.last().unwrap()].iter()
+                .map(|p| chs[p - 1].clone()).collect();
+        }
+        if method == SampleLoadMethod::Downmix{
+            chs = downmix(chs);
         }
+        if matches!(method, SampleLoadMethod::Stereo | SampleLoadMethod::Downmix) && chs.len() != 2{
+            return Err(format!(
+                "{r}TermDaw: SampleBank: only 2 channel samples are supported for stereo samples, found {b}{s}{r} channels.",
+                r = RED, b = BLUE, s = chs.len()
+            ));
+        }
+        if method != SampleLoadMethod::Stereo && chs.len() > 2{
+            return Err(format!(
+                "{r}TermDaw: SampleBank: only 1,2 channel samples are supported for left or right samples, found {b}{s}{r} channels. Use the downmix mode or pick channels.",
+                r = RED, b = BLUE, s = chs.len()
+            ));
+        }
+        let (l, r) = if chs.len() == 1{
+            if method == SampleLoadMethod::Left{
+                (chs.pop().unwrap(), Vec::new())
+            } else {
+                (Vec::new(), chs.pop().unwrap())
+            }
+        } else {
+            let r = chs.pop().unwrap();
+            let l = chs.pop().unwrap();
+            (l, r)
+        };
         let mut sample = match Sample::from(l, r, method){
             Ok(sample) => { sample },
             Err(e) => { return Err(e); }
         };
-        if method == SampleLoadMethod::Norm{
+        options.cut(&mut sample, sr);
+        if sample.l.is_empty(){
+            return Err(format!(
+                "{r}TermDaw: SampleBank: nothing is left of {b}\"{f}\"{r} after cutting it.",
+                r = RED, b = BLUE, f = file
+            ));
+        }
+        options.stretch(&mut sample, sr)?;
+        if method == SampleLoadMethod::Mix{
+            sample.mix_down(options.normalize);
+        } else if !options.normalize{
+            // keep the level of the file
+        } else if method == SampleLoadMethod::Norm{
             sample.normalize_seperate();
-        } else if method == SampleLoadMethod::Mix{
-            sample.mix_down();
         } else {
             sample.normalize(usize::MAX);
         }
+        options.shape(&mut sample, sr);
         // resampling
-        if sr != self.sample_rate{ // need to resample
-            match sample.resample(sr, self.sample_rate) {
+        if sr != project_sr{ // need to resample
+            match sample.resample(sr, project_sr) {
                 Ok(s) => sample = s,
                 Err(e) => return Err(e),
             }
         }
-        self.samples.push(sample);
-        self.names.insert(name, self.samples.len() - 1);
+        let loop_frames = options.loop_frames(sample.len(), project_sr);
+        Ok(CachedSample{ sample, loop_frames, sr, bd })
+    }
+
+    // Samples of multisampled instruments: mono or stereo, resampled but not normalized so the
+    // levels between the samples stay as they are. Returns the original sample rate.
+    pub fn add_instrument_sample(&mut self, name: String, file: &str) -> Result<usize, String>{
+        self.check_name(&name)?;
+        let (mut chs, sr, bd) = Self::decode(file)?;
+        if chs.len() > 2{
+            return Err(format!(
+                "{r}TermDaw: SampleBank: only 1,2 channel instrument samples are supported, found {b}{s}{r} channels in {b}\"{f}\"{r}.",
+                r = RED, b = BLUE, s = chs.len(), f = file
+            ));
+        }
+        let r = chs.pop().unwrap();
+        let l = chs.pop().unwrap_or_else(|| r.clone());
+        self.add_instrument_data(name, l, r, sr, bd)?;
+        Ok(sr)
+    }
+
+    // same as add_instrument_sample but for samples that are already decoded
+    pub fn add_instrument_data(
+        &mut self, name: String, l: Vec<f32>, r: Vec<f32>, sr: usize, bd: usize
+    ) -> Result<(), String>{
+        self.check_name(&name)?;
+        self.check_sr_bd(&name, sr, bd);
+        let mut sample = Sample::from(l, r, SampleLoadMethod::Stereo)?;
+        if sr != self.sample_rate{
+            sample = sample.resample(sr, self.sample_rate)?;
+        }
+        self.insert(name, sample, None);
         Ok(())
     }
 
     pub fn mark_dead(&mut self, name: &str){
+        // nothing points into the streams, they can go right away
+        self.streams.remove(name);
         if let Some(index) = self.names.get(name){
             self.marked.insert(*index);
         }
@@ -323,15 +890,18 @@ impl SampleBank{
         if self.marked.is_empty() { return; }
         let mut new_map = HashMap::new();
         let mut new_vec = Vec::new();
+        let mut new_loops = Vec::new();
         let names = std::mem::take(&mut self.names);
         for (name, index) in names{
             if self.marked.contains(&index) { continue; }
             let sample = std::mem::take(&mut self.samples[index]);
             new_vec.push(sample);
+            new_loops.push(self.loops[index]);
             new_map.insert(name, new_vec.len() - 1);
         }
         self.names = new_map;
         self.samples = new_vec;
+        self.loops = new_loops;
         self.marked.clear();
     }
 
@@ -339,12 +909,70 @@ impl SampleBank{
         self.names.get(name).copied()
     }
 
+    pub fn get_stream(&self, name: &str) -> Option<&StreamSource>{
+        self.streams.get(name)
+    }
+
     pub fn get_sample(&self, index: usize) -> &Sample{
         &self.samples[index]
     }
 
+    pub fn get_loop(&self, index: usize) -> Option<(usize, usize)>{
+        self.loops[index]
+    }
+
+    pub fn sample_rate(&self) -> usize{
+        self.sample_rate
+    }
+
     pub fn get_max_sr_bd(&self) -> (usize, usize){
         (self.max_sr, self.max_bd)
     }
 }
 
+
+#[cfg(test)]
+mod tests{
+    use crate::sample::*;
+
+    #[test]
+    fn downmix_surround(){
+        // 5.1: only the centre has sound
+        let mut chs = vec![vec![0.0; 2]; 6];
+        chs[2] = vec![1.0, -1.0];
+        let mix = downmix(chs);
+        assert_eq!(mix.len(), 2);
+        assert!((mix[0][0] - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.0001);
+        assert_eq!(mix[0], mix[1]);
+    }
+
+    #[test]
+    fn decode_aiff(){
+        // 16 bit mono aiff at 44100 Hz with four frames
+        let frames: [i16; 4] = [0, 16384, -16384, 32767];
+        let mut comm = Vec::new();
+        comm.extend_from_slice(&1i16.to_be_bytes());
+        comm.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+        comm.extend_from_slice(&16i16.to_be_bytes());
+        comm.extend_from_slice(&[0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0]);
+        let mut ssnd = vec![0u8; 8];
+        frames.iter().for_each(|f| ssnd.extend_from_slice(&f.to_be_bytes()));
+        let mut body = b"AIFF".to_vec();
+        for (id, chunk) in [(b"COMM", comm), (b"SSND", ssnd)]{
+            body.extend_from_slice(id);
+            body.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+            body.extend_from_slice(&chunk);
+        }
+        let mut bytes = b"FORM".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&body);
+        let path = std::env::temp_dir().join("termdaw_decode_test.aiff");
+        std::fs::write(&path, bytes).unwrap();
+        let (chs, sr, bd) = SampleBank::decode(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((chs.len(), sr, bd), (1, 44100, 16));
+        assert_eq!(chs[0].len(), 4);
+        assert!((chs[0][1] - 0.5).abs() < 0.001);
+        assert!((chs[0][2] + 0.5).abs() < 0.001);
+    }
+}
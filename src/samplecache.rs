@@ -0,0 +1,205 @@
+use std::{
+    fs,
+    io::{ Read, Write },
+    path::{ Path, PathBuf },
+    time::UNIX_EPOCH,
+};
+
+use crate::sample::Sample;
+
+const MAGIC: &[u8; 4] = b"TDSC";
+const VERSION: u32 = 1;
+
+// 64 bit FNV-1a. The file names in the cache have to stay the same across Rust releases,
+// which the hasher of the standard library does not promise.
+fn fnv1a(s: &str) -> u64{
+    s.bytes().fold(0xcbf29ce484222325, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+// A processed sample as it is put in the SampleBank, with the sample rate and bit depth of the
+// file it came from.
+pub struct CachedSample{
+    pub sample: Sample,
+    pub loop_frames: Option<(usize, usize)>,
+    pub sr: usize,
+    pub bd: usize,
+}
+
+// Processed samples on disk. Every file, its load options and the project sample rate get their
+// own cache file. The modification time and size of the file are stored in it as well, so a
+// changed file misses and overwrites its old cache file.
+pub struct SampleCache{
+    dir: PathBuf,
+}
+
+impl SampleCache{
+    pub fn new(dir: PathBuf) -> Self{
+        Self{ dir }
+    }
+
+    // (file name in the cache, full key) or None when the file can't be read
+    fn key(file: &str, options: &str, sr: usize) -> Option<(PathBuf, String)>{
+        let path = fs::canonicalize(file).ok()?;
+        let meta = fs::metadata(&path).ok()?;
+        let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+        let id = format!("{}|{}|{}", path.display(), options, sr);
+        let name = format!("{:016x}.tdsc", fnv1a(&id));
+        Some((PathBuf::from(name), format!("{}|{}|{}", id, mtime, meta.len())))
+    }
+
+    pub fn dir(&self) -> &Path{
+        &self.dir
+    }
+
+    // Where the float wav a file is streamed from after processing goes. The name starts with
+    // the hash of the cache file name and ends with the hash of the full key, the modification
+    // time included, so a changed file gets converted again.
+    pub fn stream_file(dir: &Path, file: &str, options: &str, sr: usize) -> Option<PathBuf>{
+        let (name, key) = Self::key(file, options, sr)?;
+        let name = name.with_extension("");
+        Some(dir.join(format!("{}-{:016x}.wav", name.display(), fnv1a(&key))))
+    }
+
+    // removes the streams of earlier versions of the same file next to a freshly written one
+    pub fn remove_old_streams(path: &Path){
+        let (dir, name) = match (path.parent(), path.file_name().and_then(|n| n.to_str())){
+            (Some(dir), Some(name)) => (dir, name),
+            _ => return,
+        };
+        let prefix = match name.split_once('-'){
+            Some((prefix, _)) => format!("{}-", prefix),
+            None => return,
+        };
+        let entries = if let Ok(entries) = fs::read_dir(dir) { entries } else { return; };
+        for entry in entries.flatten(){
+            let other = entry.file_name();
+            let other = other.to_string_lossy();
+            if other != name && other.starts_with(&prefix) && other.ends_with(".wav"){
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    pub fn get(&self, file: &str, options: &str, sr: usize) -> Option<CachedSample>{
+        let (name, key) = Self::key(file, options, sr)?;
+        let mut bytes = Vec::new();
+        fs::File::open(self.dir.join(name)).ok()?.read_to_end(&mut bytes).ok()?;
+        decode(&bytes, &key)
+    }
+
+    pub fn put(&self, file: &str, options: &str, sr: usize, cached: &CachedSample)
+        -> Result<(), String>
+    {
+        let (name, key) = if let Some(key) = Self::key(file, options, sr) { key }
+            else { return Err(format!("could not read the metadata of \"{}\"", file)); };
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        // write next to it first so a crash never leaves half a cache file
+        let path = self.dir.join(name);
+        let tmp = path.with_extension("tmp");
+        let mut out = fs::File::create(&tmp).map_err(|e| e.to_string())?;
+        out.write_all(&encode(cached, &key)).map_err(|e| e.to_string())?;
+        fs::rename(&tmp, &path).map_err(|e| e.to_string())
+    }
+}
+
+fn encode(cached: &CachedSample, key: &str) -> Vec<u8>{
+    let s = &cached.sample;
+    let mut out = Vec::with_capacity(64 + key.len() + s.len() * 8);
+    let u64 = |out: &mut Vec<u8>, x: u64| out.extend_from_slice(&x.to_le_bytes());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    u64(&mut out, key.len() as u64);
+    out.extend_from_slice(key.as_bytes());
+    u64(&mut out, cached.sr as u64);
+    u64(&mut out, cached.bd as u64);
+    let (a, b) = cached.loop_frames.map_or((u64::MAX, u64::MAX), |(a, b)| (a as u64, b as u64));
+    u64(&mut out, a);
+    u64(&mut out, b);
+    u64(&mut out, s.len() as u64);
+    s.l.iter().chain(s.r.iter()).for_each(|x| out.extend_from_slice(&x.to_le_bytes()));
+    out
+}
+
+struct Reader<'a>{
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_>{
+    fn take(&mut self, n: usize) -> Option<&[u8]>{
+        let slice = self.bytes.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(slice)
+    }
+
+    fn u64(&mut self) -> Option<u64>{
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+fn decode(bytes: &[u8], key: &str) -> Option<CachedSample>{
+    let mut reader = Reader{ bytes, pos: 0 };
+    if reader.take(4)? != MAGIC { return None; }
+    if u32::from_le_bytes(reader.take(4)?.try_into().ok()?) != VERSION { return None; }
+    let key_len = reader.u64()? as usize;
+    if reader.take(key_len)? != key.as_bytes() { return None; }
+    let sr = reader.u64()? as usize;
+    let bd = reader.u64()? as usize;
+    let (a, b) = (reader.u64()?, reader.u64()?);
+    let len = reader.u64()? as usize;
+    let floats = reader.take(len.checked_mul(8)?)?;
+    let mut data = floats.chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap()));
+    let l = data.by_ref().take(len).collect::<Vec<_>>();
+    let r = data.collect::<Vec<_>>();
+    Some(CachedSample{
+        sample: Sample{ l, r },
+        loop_frames: if a == u64::MAX { None } else { Some((a as usize, b as usize)) },
+        sr,
+        bd,
+    })
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::samplecache::*;
+
+    #[test]
+    fn roundtrip(){
+        let cached = CachedSample{
+            sample: Sample{ l: vec![0.5, -0.25, 1.0], r: vec![0.0, 0.125, -1.0] },
+            loop_frames: Some((1, 3)),
+            sr: 48000,
+            bd: 24,
+        };
+        let bytes = encode(&cached, "key");
+        let back = decode(&bytes, "key").unwrap();
+        assert_eq!(back.sample, cached.sample);
+        assert_eq!(back.loop_frames, Some((1, 3)));
+        assert_eq!((back.sr, back.bd), (48000, 24));
+        // another key or a cut off file misses
+        assert!(decode(&bytes, "other").is_none());
+        assert!(decode(&bytes[..bytes.len() - 1], "key").is_none());
+    }
+
+    #[test]
+    fn stable_names(){
+        // the file names must not change with the toolchain
+        assert_eq!(fnv1a(""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a("a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn old_streams_are_removed(){
+        let dir = std::env::temp_dir().join("termdaw_cache_test");
+        fs::create_dir_all(&dir).unwrap();
+        let names = ["0123456789abcdef-1.wav", "0123456789abcdef-2.wav", "fedcba9876543210-1.wav"];
+        for name in names{
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        SampleCache::remove_old_streams(&dir.join(names[1]));
+        assert!(!dir.join(names[0]).exists());
+        assert!(dir.join(names[1]).exists());
+        assert!(dir.join(names[2]).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
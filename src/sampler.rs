@@ -0,0 +1,123 @@
+use crate::adsr::AdsrConf;
+
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interpolation{ Linear, Cubic, Sinc }
+
+impl Interpolation{
+    pub fn from(string: &str) -> Self{
+        match string{
+            "linear" => Interpolation::Linear,
+            "sinc" => Interpolation::Sinc,
+            _ => Interpolation::Cubic,
+        }
+    }
+}
+
+// half the amount of taps of the sinc interpolator
+const SINC_HALF: isize = 8;
+
+// the loop is [start, end) in seconds
+pub struct SamplerConf{
+    pub root_note: f32,
+    pub interpolation: Interpolation,
+    pub adsr: Option<AdsrConf>,
+    pub loop_sec: Option<(f32, f32)>,
+}
+
+// without an adsr notes stop this fast after their note off
+pub const GATE_RELEASE_SEC: f32 = 0.01;
+
+impl SamplerConf{
+    pub fn release_sec(&self) -> f32{
+        if let Some(adsr) = &self.adsr { adsr.release_sec }
+        else { GATE_RELEASE_SEC }
+    }
+
+    // the loop in frames, None if there is no valid loop
+    pub fn loop_frames(&self, len: usize, sr: usize) -> Option<(usize, usize)>{
+        let (start, end) = self.loop_sec?;
+        let start = (start.max(0.0) * sr as f32) as usize;
+        let end = ((end.max(0.0) * sr as f32) as usize).min(len);
+        if end > start + 1 { Some((start, end)) }
+        else { None }
+    }
+}
+
+// frame i of data where everything from the loop end on wraps back into the loop
+#[inline]
+fn frame(data: &[f32], i: isize, lp: Option<(usize, usize)>) -> f32{
+    if i < 0 { return 0.0; }
+    let mut i = i as usize;
+    if let Some((start, end)) = lp{
+        if i >= end{
+            i = start + (i - start) % (end - start);
+        }
+    }
+    if i < data.len() { data[i] } else { 0.0 }
+}
+
+// read data at a fractional position, ratio is the playback speed. When it is above one the
+// sinc interpolator lowers its cut off so it does not alias.
+#[inline]
+pub fn read_interpolated(
+    data: &[f32], pos: f64, ratio: f32, interpolation: Interpolation, lp: Option<(usize, usize)>
+) -> f32{
+    let whole = pos.floor() as isize;
+    let f = (pos - pos.floor()) as f32;
+    let x = |o: isize| frame(data, whole + o, lp);
+    match interpolation{
+        Interpolation::Linear => {
+            let (a, b) = (x(0), x(1));
+            a + f * (b - a)
+        },
+        // catmull-rom
+        Interpolation::Cubic => {
+            let (xm, x0, x1, x2) = (x(-1), x(0), x(1), x(2));
+            let c1 = 0.5 * (x1 - xm);
+            let c2 = xm - 2.5 * x0 + 2.0 * x1 - 0.5 * x2;
+            let c3 = 0.5 * (x2 - xm) + 1.5 * (x0 - x1);
+            ((c3 * f + c2) * f + c1) * f + x0
+        },
+        // blackman windowed sinc
+        Interpolation::Sinc => {
+            let cutoff = (1.0 / ratio).min(1.0);
+            let mut acc = 0.0;
+            for o in (-SINC_HALF + 1)..=SINC_HALF{
+                let d = o as f32 - f;
+                let sinc = if d.abs() < 1e-6 { cutoff }
+                else { (PI * cutoff * d).sin() / (PI * d) };
+                let w = (d / SINC_HALF as f32 + 1.0) * 0.5;
+                let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+                acc += x(o) * sinc * window;
+            }
+            acc
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::sampler::*;
+
+    #[test]
+    fn interpolation_hits_the_frames(){
+        let data = (0..64).map(|i| (i as f32 * 0.3).sin()).collect::<Vec<_>>();
+        for interpolation in [Interpolation::Linear, Interpolation::Cubic, Interpolation::Sinc]{
+            for i in 10..50{
+                let y = read_interpolated(&data, i as f64, 1.0, interpolation, None);
+                assert!((y - data[i]).abs() < 0.001);
+            }
+        }
+        let y = read_interpolated(&data, 20.5, 1.0, Interpolation::Linear, None);
+        assert!((y - (data[20] + data[21]) * 0.5).abs() < 0.0001);
+    }
+
+    #[test]
+    fn loop_wraps(){
+        let data = (0..10).map(|i| i as f32).collect::<Vec<_>>();
+        let y = read_interpolated(&data, 12.0, 1.0, Interpolation::Linear, Some((4, 8)));
+        assert!((y - 4.0).abs() < 0.0001);
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+
+use zen_colour::*;
+
+use crate::{
+    adsr::AdsrConf,
+    instrument::{ Zone, LoopMode },
+};
+
+// generator operators we use, see the SoundFont 2.04 specification section 8.1.2
+const GEN_START_OFFSET: u16 = 0;
+const GEN_END_OFFSET: u16 = 1;
+const GEN_LOOP_START_OFFSET: u16 = 2;
+const GEN_LOOP_END_OFFSET: u16 = 3;
+const GEN_START_COARSE_OFFSET: u16 = 4;
+const GEN_END_COARSE_OFFSET: u16 = 12;
+const GEN_PAN: u16 = 17;
+const GEN_ATTACK: u16 = 34;
+const GEN_HOLD: u16 = 35;
+const GEN_DECAY: u16 = 36;
+const GEN_SUSTAIN: u16 = 37;
+const GEN_RELEASE: u16 = 38;
+const GEN_INSTRUMENT: u16 = 41;
+const GEN_KEY_RANGE: u16 = 43;
+const GEN_VEL_RANGE: u16 = 44;
+const GEN_LOOP_START_COARSE_OFFSET: u16 = 45;
+const GEN_ATTENUATION: u16 = 48;
+const GEN_LOOP_END_COARSE_OFFSET: u16 = 50;
+const GEN_COARSE_TUNE: u16 = 51;
+const GEN_FINE_TUNE: u16 = 52;
+const GEN_SAMPLE_ID: u16 = 53;
+const GEN_SAMPLE_MODES: u16 = 54;
+const GEN_SCALE_TUNING: u16 = 56;
+const GEN_ROOT_KEY: u16 = 58;
+
+const SAMPLE_TYPE_RIGHT: u16 = 2;
+const SAMPLE_TYPE_LEFT: u16 = 4;
+
+pub struct Sf2Sample{
+    pub start: usize,
+    pub end: usize,
+    pub loop_start: usize,
+    pub loop_end: usize,
+    pub sample_rate: usize,
+    pub original_pitch: u8,
+    pub pitch_correction: i8,
+    pub sample_type: u16,
+}
+
+pub struct Sf2Preset{
+    pub name: String,
+    pub bank: u16,
+    pub program: u16,
+    // zones with the index of their sample, frames are in the sample rate of the sample
+    pub zones: Vec<(Zone, usize)>,
+}
+
+pub struct Sf2{
+    // all sample data of the file, 16 bit
+    pub data: Vec<i16>,
+    pub samples: Vec<Sf2Sample>,
+    pub presets: Vec<Sf2Preset>,
+}
+
+fn err(path: &str, what: &str) -> String{
+    format!("{r}TermDaw: SF2: {w} in {b}\"{f}\"{r}.", r = RED, b = BLUE, f = path, w = what)
+}
+
+fn u16_at(b: &[u8], i: usize) -> u16{ u16::from_le_bytes([b[i], b[i + 1]]) }
+fn u32_at(b: &[u8], i: usize) -> u32{ u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]) }
+
+fn name_at(b: &[u8], i: usize) -> String{
+    let raw = &b[i..i + 20];
+    let end = raw.iter().position(|c| *c == 0).unwrap_or(20);
+    String::from_utf8_lossy(&raw[..end]).trim().to_owned()
+}
+
+// (id, data) of the chunks in a riff list body
+fn chunks(mut b: &[u8]) -> Vec<(&[u8], &[u8])>{
+    let mut res = Vec::new();
+    while b.len() >= 8{
+        let size = u32_at(b, 4) as usize;
+        let end = (8 + size).min(b.len());
+        res.push((&b[..4], &b[8..end]));
+        // chunks are padded to an even size
+        b = &b[(end + (size & 1)).min(b.len())..];
+    }
+    res
+}
+
+type Gens = HashMap<u16, u16>;
+
+// the generators of every bag in [bags[i], bags[i + 1]), bags hold indices into gens
+fn zone_gens(bags: &[u8], gens: &[u8], from: usize, to: usize) -> Vec<Gens>{
+    let mut res = Vec::new();
+    for bag in from..to{
+        if (bag + 1) * 4 + 2 > bags.len() { break; }
+        let g0 = u16_at(bags, bag * 4) as usize;
+        let g1 = u16_at(bags, bag * 4 + 4) as usize;
+        let mut zone = HashMap::new();
+        for g in g0..g1{
+            if g * 4 + 4 > gens.len() { break; }
+            zone.insert(u16_at(gens, g * 4), u16_at(gens, g * 4 + 2));
+        }
+        res.push(zone);
+    }
+    res
+}
+
+// a global zone is a first zone without the terminal generator
+fn split_global(mut zones: Vec<Gens>, terminal: u16) -> (Gens, Vec<Gens>){
+    if !zones.is_empty() && !zones[0].contains_key(&terminal){
+        let global = zones.remove(0);
+        (global, zones)
+    } else {
+        (HashMap::new(), zones)
+    }
+}
+
+fn range(gens: &Gens, op: u16) -> (u8, u8){
+    gens.get(&op).map(|v| ((v & 0xff) as u8, (v >> 8) as u8)).unwrap_or((0, 127))
+}
+
+fn timecents_sec(tc: i32) -> f32{
+    2.0f32.powf(tc as f32 / 1200.0)
+}
+
+fn zone_from_gens(inst: &Gens, preset: &Gens, sample: &Sf2Sample) -> Option<Zone>{
+    // instrument values are absolute, preset values are added on top
+    let i = |op: u16, default: i32| inst.get(&op).map(|v| *v as i16 as i32).unwrap_or(default);
+    let p = |op: u16| preset.get(&op).map(|v| *v as i16 as i32).unwrap_or(0);
+    let add = |op: u16, default: i32| i(op, default) + p(op);
+    let (ik, pk) = (range(inst, GEN_KEY_RANGE), range(preset, GEN_KEY_RANGE));
+    let (iv, pv) = (range(inst, GEN_VEL_RANGE), range(preset, GEN_VEL_RANGE));
+    let keys = (ik.0.max(pk.0), ik.1.min(pk.1));
+    let vels = (iv.0.max(pv.0), iv.1.min(pv.1));
+    if keys.0 > keys.1 || vels.0 > vels.1 { return None; }
+
+    let start = sample.start as i64
+        + i(GEN_START_OFFSET, 0) as i64 + i(GEN_START_COARSE_OFFSET, 0) as i64 * 32768;
+    let end = sample.end as i64
+        + i(GEN_END_OFFSET, 0) as i64 + i(GEN_END_COARSE_OFFSET, 0) as i64 * 32768;
+    let loop_start = sample.loop_start as i64
+        + i(GEN_LOOP_START_OFFSET, 0) as i64 + i(GEN_LOOP_START_COARSE_OFFSET, 0) as i64 * 32768;
+    let loop_end = sample.loop_end as i64
+        + i(GEN_LOOP_END_OFFSET, 0) as i64 + i(GEN_LOOP_END_COARSE_OFFSET, 0) as i64 * 32768;
+    let base = sample.start as i64;
+    let rel = |x: i64| (x - base).max(0) as usize;
+
+    let root = i(GEN_ROOT_KEY, -1);
+    let root_key = if root >= 0 { root } else { sample.original_pitch as i32 };
+    let pan = match sample.sample_type{
+        SAMPLE_TYPE_LEFT => -1.0,
+        SAMPLE_TYPE_RIGHT => 1.0,
+        _ => (add(GEN_PAN, 0) as f32 / 500.0).clamp(-1.0, 1.0),
+    };
+    let loop_mode = match i(GEN_SAMPLE_MODES, 0) & 3{
+        1 => LoopMode::Continuous,
+        3 => LoopMode::Sustain,
+        _ => LoopMode::None,
+    };
+    let sustain_cb = add(GEN_SUSTAIN, 0).clamp(0, 1440);
+    Some(Zone{
+        sample: String::new(),
+        key_range: (keys.0 as f32, keys.1 as f32),
+        vel_range: (vels.0 as f32 / 127.0, vels.1 as f32 / 127.0),
+        root_key: root_key as f32,
+        key_track: add(GEN_SCALE_TUNING, 100) as f32 / 100.0,
+        tune_cents: (add(GEN_COARSE_TUNE, 0) * 100 + add(GEN_FINE_TUNE, 0)) as f32
+            + sample.pitch_correction as f32,
+        gain: 10.0f32.powf(-add(GEN_ATTENUATION, 0).max(0) as f32 / 200.0),
+        pan,
+        offset: rel(start),
+        loop_mode,
+        loop_frames: if loop_end > loop_start + 1 { Some((rel(loop_start), rel(loop_end))) }
+            else { None },
+        adsr: AdsrConf::hit_conf(
+            timecents_sec(add(GEN_ATTACK, -12000)).max(0.001),
+            timecents_sec(add(GEN_HOLD, -12000)),
+            1.0,
+            timecents_sec(add(GEN_DECAY, -12000)),
+            10.0f32.powf(-sustain_cb as f32 / 200.0),
+            timecents_sec(add(GEN_RELEASE, -12000)).max(0.001),
+        ),
+        seq_length: 1,
+        seq_position: 1,
+    }).filter(|_| end > start)
+}
+
+pub fn load_sf2(path: &str) -> Result<Sf2, String>{
+    let bytes = std::fs::read(path).map_err(|_| err(path, "could not read file"))?;
+    parse_sf2(&bytes, path)
+}
+
+fn parse_sf2(bytes: &[u8], path: &str) -> Result<Sf2, String>{
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"sfbk"{
+        return Err(err(path, "not a SoundFont 2 file"));
+    }
+    let mut smpl: &[u8] = &[];
+    let mut pdta = HashMap::new();
+    for (id, body) in chunks(&bytes[12..]){
+        if id != b"LIST" || body.len() < 4 { continue; }
+        for (sub, data) in chunks(&body[4..]){
+            match (&body[..4], sub){
+                (b"sdta", b"smpl") => smpl = data,
+                (b"pdta", id) => { pdta.insert(id.to_vec(), data); },
+                _ => {},
+            }
+        }
+    }
+    let get = |id: &[u8]| pdta.get(id).copied().ok_or_else(|| err(path, "missing hydra chunks"));
+    let (phdr, pbag, pgen) = (get(b"phdr")?, get(b"pbag")?, get(b"pgen")?);
+    let (inst, ibag, igen, shdr) = (get(b"inst")?, get(b"ibag")?, get(b"igen")?, get(b"shdr")?);
+
+    let data = smpl.chunks_exact(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect::<Vec<_>>();
+    let mut samples = Vec::new();
+    // the last record of every hydra chunk is a terminal one
+    for s in 0..(shdr.len() / 46).saturating_sub(1){
+        let b = &shdr[s * 46..];
+        samples.push(Sf2Sample{
+            start: u32_at(b, 20) as usize,
+            end: (u32_at(b, 24) as usize).min(data.len()),
+            loop_start: u32_at(b, 28) as usize,
+            loop_end: u32_at(b, 32) as usize,
+            sample_rate: (u32_at(b, 36) as usize).max(1),
+            original_pitch: if b[40] > 127 { 60 } else { b[40] },
+            pitch_correction: b[41] as i8,
+            sample_type: u16_at(b, 44),
+        });
+    }
+
+    let n_inst = (inst.len() / 22).saturating_sub(1);
+    let instruments = (0..n_inst).map(|k| {
+        let from = u16_at(inst, k * 22 + 20) as usize;
+        let to = u16_at(inst, k * 22 + 42) as usize;
+        split_global(zone_gens(ibag, igen, from, to), GEN_SAMPLE_ID)
+    }).collect::<Vec<_>>();
+
+    let mut presets = Vec::new();
+    for k in 0..(phdr.len() / 38).saturating_sub(1){
+        let b = &phdr[k * 38..];
+        let from = u16_at(b, 24) as usize;
+        let to = u16_at(b, 38 + 24) as usize;
+        let (pglobal, pzones) = split_global(zone_gens(pbag, pgen, from, to), GEN_INSTRUMENT);
+        let mut zones = Vec::new();
+        for pzone in pzones{
+            let mut preset_gens = pglobal.clone();
+            preset_gens.extend(pzone);
+            let instrument = if let Some(i) = preset_gens.get(&GEN_INSTRUMENT) { *i as usize }
+                else { continue; };
+            let (iglobal, izones) = if let Some(x) = instruments.get(instrument) { x }
+                else { continue; };
+            for izone in izones{
+                let mut inst_gens = iglobal.clone();
+                inst_gens.extend(izone.iter().map(|(k, v)| (*k, *v)));
+                let sample_index = if let Some(i) = inst_gens.get(&GEN_SAMPLE_ID) { *i as usize }
+                    else { continue; };
+                let sample = if let Some(s) = samples.get(sample_index) { s } else { continue; };
+                if let Some(zone) = zone_from_gens(&inst_gens, &preset_gens, sample){
+                    zones.push((zone, sample_index));
+                }
+            }
+        }
+        presets.push(Sf2Preset{
+            name: name_at(b, 0),
+            program: u16_at(b, 20),
+            bank: u16_at(b, 22),
+            zones,
+        });
+    }
+    if presets.is_empty(){
+        return Err(err(path, "no presets found"));
+    }
+    Ok(Sf2{ data, samples, presets })
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::sf2::*;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8>{
+        let mut res = id.to_vec();
+        res.extend((data.len() as u32).to_le_bytes());
+        res.extend(data);
+        if data.len() & 1 == 1 { res.push(0); }
+        res
+    }
+
+    fn list(kind: &[u8], chunks: &[Vec<u8>]) -> Vec<u8>{
+        let mut body = kind.to_vec();
+        chunks.iter().for_each(|c| body.extend(c));
+        chunk(b"LIST", &body)
+    }
+
+    fn named(name: &str, rest: &[u8]) -> Vec<u8>{
+        let mut res = name.as_bytes().to_vec();
+        res.resize(20, 0);
+        res.extend(rest);
+        res
+    }
+
+    fn words(ws: &[u16]) -> Vec<u8>{
+        ws.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    fn shdr(name: &str, vals: [u32; 5], pitch: u8, ty: u16) -> Vec<u8>{
+        let mut rest: Vec<u8> = vals.iter().flat_map(|v| v.to_le_bytes()).collect();
+        rest.extend([pitch, 0]);
+        rest.extend(words(&[0, ty]));
+        named(name, &rest)
+    }
+
+    #[test]
+    fn parses_a_minimal_bank(){
+        let smpl = (0..100i16).flat_map(|x| (x * 100).to_le_bytes()).collect::<Vec<_>>();
+        let phdr = [
+            named("Piano", &[words(&[5, 0, 0]), vec![0; 12]].concat()),
+            named("EOP", &[words(&[0, 0, 2]), vec![0; 12]].concat()),
+        ].concat();
+        // one global preset zone that lowers the pitch, one with the instrument
+        let pbag = words(&[0, 0, 1, 0, 2, 0]);
+        let pgen = words(&[GEN_COARSE_TUNE, (-12i16) as u16, GEN_INSTRUMENT, 0, 0, 0]);
+        // a zone with the sample and a later zone without one, which is ignored
+        let inst = [named("Inst", &words(&[0])), named("EOI", &words(&[2]))].concat();
+        let ibag = words(&[0, 0, 4, 0, 5, 0]);
+        let igen = words(&[
+            GEN_KEY_RANGE, 40 | (70 << 8), GEN_ROOT_KEY, 64, GEN_SAMPLE_MODES, 1,
+            GEN_SAMPLE_ID, 0, GEN_KEY_RANGE, 80 | (90 << 8), 0, 0,
+        ]);
+        let shdr = [
+            shdr("Smp", [10, 90, 20, 80, 22050], 60, 1),
+            shdr("EOS", [0; 5], 0, 0),
+        ].concat();
+        let pdta = [
+            chunk(b"phdr", &phdr), chunk(b"pbag", &pbag), chunk(b"pmod", &[0; 10]),
+            chunk(b"pgen", &pgen), chunk(b"inst", &inst), chunk(b"ibag", &ibag),
+            chunk(b"imod", &[0; 10]), chunk(b"igen", &igen), chunk(b"shdr", &shdr),
+        ];
+        let mut body = b"sfbk".to_vec();
+        body.extend(list(b"INFO", &[]));
+        body.extend(list(b"sdta", &[chunk(b"smpl", &smpl)]));
+        body.extend(list(b"pdta", &pdta));
+        let bytes = chunk(b"RIFF", &body);
+
+        let sf2 = parse_sf2(&bytes, "test").unwrap();
+        assert_eq!(sf2.data.len(), 100);
+        assert_eq!(sf2.samples.len(), 1);
+        assert_eq!(sf2.presets.len(), 1);
+        let preset = &sf2.presets[0];
+        assert_eq!((preset.name.as_str(), preset.bank, preset.program), ("Piano", 0, 5));
+        assert_eq!(preset.zones.len(), 1);
+        let (zone, sample) = &preset.zones[0];
+        assert_eq!(*sample, 0);
+        assert_eq!(zone.key_range, (40.0, 70.0));
+        assert_eq!(zone.root_key, 64.0);
+        assert_eq!(zone.tune_cents, -1200.0);
+        assert_eq!(zone.loop_mode, LoopMode::Continuous);
+        assert_eq!(zone.loop_frames, Some((10, 70)));
+        assert_eq!(zone.offset, 0);
+    }
+}
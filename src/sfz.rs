@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    path::{ Path, PathBuf },
+};
+
+use zen_colour::*;
+
+use crate::{
+    adsr::AdsrConf,
+    instrument::{ Zone, LoopMode },
+};
+
+// midi note number of a number or a note name like "c4", "f#3" or "eb-1", c4 is 60
+pub fn parse_key(string: &str) -> Option<f32>{
+    let string = string.trim().to_lowercase();
+    if let Ok(n) = string.parse::<f32>(){
+        return Some(n);
+    }
+    let mut chars = string.chars();
+    let pc = match chars.next()?{
+        'c' => 0, 'd' => 2, 'e' => 4, 'f' => 5, 'g' => 7, 'a' => 9, 'b' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (acc, rest) = if let Some(rest) = rest.strip_prefix('#') { (1, rest) }
+        else if let Some(rest) = rest.strip_prefix('b') { (-1, rest) }
+        else { (0, rest) };
+    let octave = rest.parse::<i32>().ok()?;
+    Some(((octave + 1) * 12 + pc + acc) as f32)
+}
+
+fn strip_comments(text: &str) -> String{
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    loop{
+        let line = rest.find("//");
+        let block = rest.find("/*");
+        match (line, block){
+            (Some(l), b) if b.is_none_or(|b| l < b) => {
+                out.push_str(&rest[..l]);
+                rest = rest[l..].find('\n').map_or("", |e| &rest[l + e..]);
+            },
+            (_, Some(b)) => {
+                out.push_str(&rest[..b]);
+                rest = rest[b..].find("*/").map_or("", |e| &rest[b + e + 2..]);
+                out.push(' ');
+            },
+            _ => {
+                out.push_str(rest);
+                return out;
+            },
+        }
+    }
+}
+
+// comments out, #include inlined and #define substituted
+fn preprocess(path: &Path, defines: &mut Vec<(String, String)>, depth: usize)
+    -> Result<String, String>
+{
+    let text = std::fs::read_to_string(path).map_err(|_| format!(
+        "{r}TermDaw: SFZ: could not read file {b}\"{f}\"{r}.",
+        r = RED, b = BLUE, f = path.display()
+    ))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut out = String::new();
+    for line in strip_comments(&text).lines(){
+        let mut line = line.to_owned();
+        for (name, value) in defines.iter(){
+            line = line.replace(name, value);
+        }
+        let trimmed = line.trim();
+        if let Some(def) = trimmed.strip_prefix("#define"){
+            let mut parts = def.split_whitespace();
+            if let (Some(name), Some(value)) = (parts.next(), parts.next()){
+                defines.push((name.to_owned(), value.to_owned()));
+                // longest names first so $A does not eat $AB
+                defines.sort_by_key(|d| std::cmp::Reverse(d.0.len()));
+            }
+        } else if let Some(inc) = trimmed.strip_prefix("#include"){
+            if depth > 16{
+                return Err(format!("{}TermDaw: SFZ: #include nested too deep.", RED));
+            }
+            let inc = inc.trim().trim_matches('"');
+            out.push_str(&preprocess(&dir.join(inc), defines, depth + 1)?);
+            out.push('\n');
+        } else {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+// split into headers with their opcodes, values may contain spaces (sample paths)
+fn parse_headers(text: &str) -> Vec<(String, Vec<(String, String)>)>{
+    let mut headers = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('<'){
+        let end = if let Some(end) = rest[start..].find('>') { start + end } else { break; };
+        let header = rest[start + 1..end].trim().to_lowercase();
+        rest = &rest[end + 1..];
+        let body_end = rest.find('<').unwrap_or(rest.len());
+        let mut opcodes: Vec<(String, String)> = Vec::new();
+        for token in rest[..body_end].split_whitespace(){
+            if let Some((key, value)) = token.split_once('='){
+                opcodes.push((key.to_lowercase(), value.to_owned()));
+            } else if let Some(last) = opcodes.last_mut(){
+                last.1.push(' ');
+                last.1.push_str(token);
+            }
+        }
+        headers.push((header, opcodes));
+    }
+    headers
+}
+
+fn zone_from_opcodes(ops: &HashMap<String, String>) -> Zone{
+    let mut zone = Zone::default();
+    let f = |key: &str| ops.get(key).and_then(|v| v.trim().parse::<f32>().ok());
+    let key = |key: &str| ops.get(key).and_then(|v| parse_key(v));
+    if let Some(k) = key("key"){
+        zone.key_range = (k, k);
+        zone.root_key = k;
+    }
+    if let Some(k) = key("lokey") { zone.key_range.0 = k; }
+    if let Some(k) = key("hikey") { zone.key_range.1 = k; }
+    if let Some(k) = key("pitch_keycenter") { zone.root_key = k; }
+    if let Some(v) = f("lovel") { zone.vel_range.0 = v / 127.0; }
+    if let Some(v) = f("hivel") { zone.vel_range.1 = v / 127.0; }
+    if let Some(v) = f("pitch_keytrack") { zone.key_track = v / 100.0; }
+    zone.tune_cents = f("tune").unwrap_or(0.0) + f("transpose").unwrap_or(0.0) * 100.0;
+    if let Some(v) = f("volume") { zone.gain = 10.0f32.powf(v / 20.0); }
+    if let Some(v) = f("pan") { zone.pan = (v / 100.0).clamp(-1.0, 1.0); }
+    if let Some(v) = f("offset") { zone.offset = v.max(0.0) as usize; }
+    let loop_start = f("loop_start").or_else(|| f("loopstart"));
+    // the loop end is inclusive in sfz
+    let loop_end = f("loop_end").or_else(|| f("loopend"));
+    if let (Some(s), Some(e)) = (loop_start, loop_end){
+        if e > s { zone.loop_frames = Some((s.max(0.0) as usize, e as usize + 1)); }
+    }
+    let loop_mode = ops.get("loop_mode").or_else(|| ops.get("loopmode"));
+    zone.loop_mode = match loop_mode.map(|s| s.trim()){
+        Some("one_shot") => LoopMode::OneShot,
+        Some("loop_continuous") => LoopMode::Continuous,
+        Some("loop_sustain") => LoopMode::Sustain,
+        Some(_) => LoopMode::None,
+        // we don't read loops from the wav files, so loop points mean looping
+        None => if zone.loop_frames.is_some() { LoopMode::Continuous } else { LoopMode::None },
+    };
+    if let Some(v) = f("seq_length") { zone.seq_length = (v as usize).max(1); }
+    if let Some(v) = f("seq_position") { zone.seq_position = (v as usize).max(1); }
+    let sustain = (f("ampeg_sustain").unwrap_or(100.0) / 100.0).clamp(0.0, 1.0);
+    zone.adsr = AdsrConf::hit_conf(
+        f("ampeg_attack").unwrap_or(0.0).max(0.001),
+        f("ampeg_hold").unwrap_or(0.0).max(0.0),
+        1.0,
+        f("ampeg_decay").unwrap_or(0.0).max(0.0),
+        sustain,
+        f("ampeg_release").unwrap_or(0.001).max(0.001),
+    );
+    zone
+}
+
+// Reads the regions of an sfz file: the sample file of every region and its zone. Frames of the
+// zones are still in the sample rate of the sample files.
+pub fn load_sfz(path: &str) -> Result<Vec<(String, Zone)>, String>{
+    let path = Path::new(path);
+    let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+    let text = preprocess(path, &mut Vec::new(), 0)?;
+    let mut default_path = PathBuf::new();
+    let mut global = HashMap::new();
+    let mut master = HashMap::new();
+    let mut group = HashMap::new();
+    let mut regions = Vec::new();
+    for (header, opcodes) in parse_headers(&text){
+        let ops = opcodes.into_iter();
+        match header.as_str(){
+            "control" => {
+                for (key, value) in ops{
+                    if key == "default_path" { default_path = PathBuf::from(value.trim()); }
+                }
+            },
+            "global" => {
+                global = ops.collect();
+                master.clear();
+                group.clear();
+            },
+            "master" => {
+                master = ops.collect();
+                group.clear();
+            },
+            "group" => { group = ops.collect(); },
+            "region" => {
+                let mut merged = global.clone();
+                merged.extend(master.clone());
+                merged.extend(group.clone());
+                merged.extend(ops);
+                let sample = if let Some(sample) = merged.get("sample") { sample.trim() }
+                else { continue; };
+                // generated sounds like *sine are not supported
+                if sample.starts_with('*') { continue; }
+                let file = dir.join(&default_path).join(sample.replace('\\', "/"));
+                regions.push((file.to_string_lossy().into_owned(), zone_from_opcodes(&merged)));
+            },
+            _ => {},
+        }
+    }
+    if regions.is_empty(){
+        return Err(format!(
+            "{r}TermDaw: SFZ: no regions with samples found in {b}\"{f}\"{r}.",
+            r = RED, b = BLUE, f = path.display()
+        ));
+    }
+    Ok(regions)
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::sfz::*;
+
+    #[test]
+    fn key_names(){
+        assert_eq!(parse_key("60"), Some(60.0));
+        assert_eq!(parse_key("c4"), Some(60.0));
+        assert_eq!(parse_key("C#4"), Some(61.0));
+        assert_eq!(parse_key("eb3"), Some(51.0));
+        assert_eq!(parse_key("c-1"), Some(0.0));
+        assert_eq!(parse_key("h2"), None);
+    }
+
+    #[test]
+    fn headers_and_opcodes(){
+        let text = strip_comments(
+            "<group> lokey=40 // comment\n<region> sample=a b.wav hikey=c4 /* x */ <region>key=1"
+        );
+        let headers = parse_headers(&text);
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers[1].1[0], ("sample".to_owned(), "a b.wav".to_owned()));
+        assert_eq!(headers[1].1[1], ("hikey".to_owned(), "c4".to_owned()));
+        assert_eq!(headers[2].1[0], ("key".to_owned(), "1".to_owned()));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::sample::Sample;
+
+// (start, end) frames of every slice
+pub type Slices = Vec<(usize, usize)>;
+
+// onsets closer together than this are merged
+const MIN_SLICE_SEC: f32 = 0.05;
+const FRAME: usize = 1024;
+const HOP: usize = 256;
+
+// n slices of (nearly) the same length
+pub fn even_slices(len: usize, n: usize) -> Slices{
+    let n = n.max(1).min(len.max(1));
+    (0..n).map(|i| (i * len / n, (i + 1) * len / n)).collect()
+}
+
+// Slices starting at the onsets of the sample. An onset is a jump in the energy of at least
+// threshold decibels compared to the frames before it. With max_slices above 0 only the
+// strongest onsets are kept.
+pub fn onset_slices(sample: &Sample, threshold_db: f32, max_slices: usize, sr: usize) -> Slices{
+    let len = sample.len();
+    if len == 0 { return Vec::new(); }
+    // energy in decibels per hop
+    let energy = (0..len.div_ceil(HOP)).map(|h| {
+        let start = h * HOP;
+        let end = (start + FRAME).min(len);
+        let sum = (start..end).map(|i| {
+            let m = (sample.l[i] + sample.r[i]) * 0.5;
+            m * m
+        }).sum::<f32>();
+        10.0 * (sum / (end - start) as f32 + 1e-10).log10()
+    }).collect::<Vec<_>>();
+    // rise of the energy compared to the quietest of the last few hops
+    let back = 4;
+    let rises = (0..energy.len()).map(|h| {
+        let floor = energy[h.saturating_sub(back)..h].iter().fold(f32::MAX, |a, b| a.min(*b));
+        if h == 0 || energy[h] < -60.0 { 0.0 } else { energy[h] - floor }
+    }).collect::<Vec<_>>();
+    // The energy frame of a hop reaches FRAME ahead, so the rise comes from the end of the
+    // frame: the hop before did not rise yet. The onset is the first frame loud enough there.
+    let onset_at = |h: usize| {
+        let end = (h * HOP + FRAME).min(len);
+        let from = (h * HOP + FRAME).saturating_sub(2 * HOP).max(h * HOP).min(end);
+        let level = 10.0f32.powf((energy[h] - rises[h] + threshold_db) / 10.0);
+        (from..end).find(|i| {
+            let m = (sample.l[*i] + sample.r[*i]) * 0.5;
+            m * m >= level
+        }).unwrap_or(from)
+    };
+    // the first hop of every rise, merged with the ones right after it
+    let min_gap = (MIN_SLICE_SEC * sr as f32) as usize;
+    let mut onsets: Vec<(usize, f32)> = Vec::new();
+    for h in 1..rises.len(){
+        if rises[h] < threshold_db { continue; }
+        match onsets.last_mut(){
+            Some(last) if (h * HOP + FRAME).saturating_sub(last.0) < min_gap => {
+                last.1 = last.1.max(rises[h]);
+            },
+            _ if rises[h - 1] < threshold_db => onsets.push((onset_at(h), rises[h])),
+            _ => {},
+        }
+    }
+    if max_slices > 0 && onsets.len() > max_slices{
+        onsets.sort_by(|a, b| b.1.total_cmp(&a.1));
+        onsets.truncate(max_slices);
+        onsets.sort_by_key(|(at, _)| *at);
+    }
+    let mut starts = onsets.into_iter().map(|(at, _)| at).collect::<Vec<_>>();
+    // whatever comes before the first onset is a slice as well when there is room for one
+    if starts.first().is_none_or(|s| *s >= min_gap)
+        && (max_slices == 0 || starts.len() < max_slices)
+    {
+        starts.insert(0, 0);
+    } else if let Some(first) = starts.first_mut(){
+        *first = 0;
+    }
+    starts.iter().enumerate()
+        .map(|(i, s)| (*s, starts.get(i + 1).copied().unwrap_or(len)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::slice::*;
+
+    #[test]
+    fn even(){
+        assert_eq!(even_slices(10, 3), vec![(0, 3), (3, 6), (6, 10)]);
+        assert_eq!(even_slices(2, 4), vec![(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn onsets(){
+        let sr = 44100;
+        let mut sample = Sample::new(sr);
+        // three bursts of noise with silence in between
+        for start in [0, 15000, 30000]{
+            for i in start..start + 5000{
+                let x = if (i * 7919) % 13 < 6 { 0.5 } else { -0.5 };
+                sample.l[i] = x;
+                sample.r[i] = x;
+            }
+        }
+        let slices = onset_slices(&sample, 12.0, 0, sr);
+        assert_eq!(slices.len(), 3);
+        assert_eq!(slices[0].0, 0);
+        assert_eq!(slices[1].0, 15000);
+        assert_eq!(slices[2].0, 30000);
+        assert_eq!(slices[2].1, sr);
+        assert_eq!(onset_slices(&sample, 12.0, 2, sr).len(), 2);
+    }
+}
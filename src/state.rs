@@ -1,6 +1,19 @@
 use crate::{
     extensions::*,
     synth::*,
+    filter::*,
+    delay::*,
+    distortion::*,
+    noise::*,
+    fm::*,
+    pluck::*,
+    drums::*,
+    sampler::*,
+    instrument::*,
+    granular::*,
+    slice::*,
+    stream::*,
+    clip::*,
     adsr::*,
     graph::*,
     floww::*,
@@ -14,6 +27,7 @@ use rubato::{
     Resampler, SincFixedIn, SincInterpolationType, SincInterpolationParameters, WindowFunction
 };
 use fnrs::vecs;
+use rayon::prelude::*;
 use mlua::prelude::*;
 use sampsyn::*;
 use zen_colour::*;
@@ -31,16 +45,19 @@ pub struct State{
     pub host: Lv2Host,
     pub fb: FlowwBank,
     pub bb: BufferBank,
+    pub ib: InstrumentBank,
     pub config: Config,
     pub contents: String,
     pub loaded: bool,
     pub cs: usize,
     pub render_sr: usize,
     pub bd: usize,
+    pub bpm: f32,
     pub output_vertex: String,
     pub output_file: String,
-    pub cur_samples: Vec<(String, String, String)>,
+    pub cur_samples: Vec<(String, String, SampleLoadOptions)>,
     pub cur_resources: Vec<(String, String)>,
+    pub cur_instruments: Vec<(String, String, String)>,
     pub cur_lv2plugins: Vec<(String, String)>,
     pub cur_lv2params: Vec<(String, String, f32)>,
     pub wdir: String,
@@ -67,16 +84,20 @@ impl State{
         }
 
         vecs!(
-            new_samples, new_resources, new_lv2plugins, new_lv2params, midis, streams,
+            new_samples, new_resources, new_sfzs, new_sf2s, new_lv2plugins, new_lv2params, midis,
+            midi_channels, slicings, streams,
             sums, norms, sampleloops, samplemultis, samplelerps, debugsines, synths, sampsyns,
             lv2fxs, adsrs,
-            bandpasses,
+            bandpasses, filters, reverbs, convolvers, delays, choruses, flangers, phasers,
+            distortions, noises, fms, vas, plucks, kicks, snares, hats,
+            samplers, sfzs, sf2s, granulars, slicers, clips,
             edges
         );
 
         let mut cs = self.cs;
         let mut render_sr = self.render_sr;
         let mut bd = self.bd;
+        let mut bpm = self.bpm;
         let mut output_file = std::mem::take(&mut self.output_file);
         let mut output_vertex = std::mem::take(&mut self.output_vertex);
 
@@ -106,14 +127,23 @@ impl State{
             })?)?;
             setter!("set_render_samplerate", usize, render_sr);
             setter!("set_render_bitdepth", usize, bd);
+            setter!("set_bpm", f32, bpm);
             setter!("set_output_file", String, output_file);
             // ---- Resources
-                // load_sample(name, file)
-            seed!("load_sample", (String, String, String), new_samples);
+                // load_sample(name, file, mode or options)
+            seed!("load_sample", (String, String, SampleLoadOptions), new_samples);
                 // load_resource(name, file)
             seed!("load_resource", (String, String), new_resources);
+                // load_sfz(name, file)
+            seed!("load_sfz", (String, String), new_sfzs);
+                // load_sf2(name, file)
+            seed!("load_sf2", (String, String), new_sf2s);
                 // load_midi(name, file)
             seed!("load_midi_floww", (String, String), midis);
+                // load_midi_channels(name, file)
+            seed!("load_midi_channels", (String, String), midi_channels);
+                // slice_sample(sample, slices, onset_threshold_db)
+            seed!("slice_sample", (String, usize, Option<f32>), slicings);
                 // declare_stream(name)
             seed!("declare_stream", String, streams);
                 // load_lv2(name, uri)
@@ -148,6 +178,114 @@ impl State{
             seed!("add_adsr", (String, f32, f32, f32, String, bool, bool, i32, Vec<f32>), adsrs);
                 // add_bandpass(name, gain, angle, wet, cut_off_hz_low, cut_off_hz_high, pass)
             seed!("add_bandpass", (String, f32, f32, f32, f32, f32, bool), bandpasses);
+                // add_filter(name, gain, angle, wet, mode, cutoff_hz, resonance, floww,
+                //  env_octaves, adsr_conf, key_track, vel_amount)
+            seed!(
+                "add_filter",
+                (
+                    String, f32, f32, f32, String, f32, f32, Option<String>, Option<f32>,
+                    Option<Vec<f32>>, Option<f32>, Option<f32>
+                ),
+                filters
+            );
+                // add_reverb(name, gain, angle, wet, room_size, damping, pre_delay_ms, width)
+            seed!("add_reverb", (String, f32, f32, f32, f32, f32, f32, f32), reverbs);
+                // add_convolver(name, gain, angle, wet, ir_sample)
+            seed!("add_convolver", (String, f32, f32, f32, String), convolvers);
+                // add_delay(name, gain, angle, wet, time_l, time_r, feedback, ping_pong,
+                //  damping_hz)
+            seed!(
+                "add_delay", (String, f32, f32, f32, String, String, f32, bool, f32), delays
+            );
+                // add_chorus(name, gain, angle, wet, rate_hz, depth_ms, feedback, stereo_phase)
+            seed!("add_chorus", (String, f32, f32, f32, f32, f32, f32, f32), choruses);
+                // add_flanger(name, gain, angle, wet, rate_hz, depth_ms, feedback, stereo_phase)
+            seed!("add_flanger", (String, f32, f32, f32, f32, f32, f32, f32), flangers);
+                // add_phaser(name, gain, angle, wet, rate_hz, depth, feedback, stereo_phase,
+                //  stages)
+            seed!(
+                "add_phaser", (String, f32, f32, f32, f32, f32, f32, f32, Option<i32>), phasers
+            );
+                // add_distortion(name, gain, angle, wet, mode, drive, bias, tone_hz,
+                //  oversampling, bits, downsample)
+            seed!(
+                "add_distortion",
+                (
+                    String, f32, f32, f32, String, f32, f32, f32, Option<i32>, Option<f32>,
+                    Option<i32>
+                ),
+                distortions
+            );
+                // add_noise(name, gain, angle, color, floww, adsr_conf, seed)
+            seed!(
+                "add_noise",
+                (String, f32, f32, String, Option<String>, Option<Vec<f32>>, Option<u64>),
+                noises
+            );
+                // add_fm(name, gain, angle, floww, operators, algorithm, feedback, matrix)
+            seed!(
+                "add_fm",
+                (String, f32, f32, String, Vec<Vec<f32>>, i32, f32, Option<Vec<Vec<f32>>>),
+                fms
+            );
+                // add_va(name, gain, angle, floww, wave, pulse_width, pwm_rate, pwm_depth,
+                //  sync_ratio, unison, detune_cents, spread, adsr_conf)
+            seed!(
+                "add_va",
+                (
+                    String, f32, f32, String, String, f32, f32, f32, f32, i32, f32, f32,
+                    Vec<f32>
+                ),
+                vas
+            );
+                // add_pluck(name, gain, angle, floww, decay_sec, damping, brightness,
+                //  pick_position, spread, release_sec, seed)
+            seed!(
+                "add_pluck",
+                (String, f32, f32, String, f32, f32, f32, f32, f32, f32, Option<u64>),
+                plucks
+            );
+                // add_kick(name, gain, angle, floww, note, pitch_start_hz, pitch_end_hz,
+                //  pitch_decay_sec, decay_sec, click)
+            seed!("add_kick", (String, f32, f32, String, i32, f32, f32, f32, f32, f32), kicks);
+                // add_snare(name, gain, angle, floww, note, tone_hz, tone_decay_sec,
+                //  noise_decay_sec, noise_hz, tone_mix)
+            seed!("add_snare", (String, f32, f32, String, i32, f32, f32, f32, f32, f32), snares);
+                // add_hat(name, gain, angle, floww, note, decay_sec, filter_hz, metallic)
+            seed!("add_hat", (String, f32, f32, String, i32, f32, f32, f32), hats);
+                // add_sampler(name, gain, angle, sample, floww, root_note, interpolation,
+                //  adsr_conf, loop_start_sec, loop_end_sec)
+            seed!(
+                "add_sampler",
+                (
+                    String, f32, f32, String, String, f32, String, Option<Vec<f32>>, Option<f32>,
+                    Option<f32>
+                ),
+                samplers
+            );
+                // add_sfz(name, gain, angle, instrument, floww)
+            seed!("add_sfz", (String, f32, f32, String, String), sfzs);
+                // add_sf2(name, gain, angle, bank, preset, floww)
+            seed!("add_sf2", (String, f32, f32, String, String, String), sf2s);
+                // add_granular(name, gain, angle, sample, floww, position_floww, grain_ms,
+                //  density, position, scan_rate, jitter_ms, pitch_spread_cents, window,
+                //  stereo_spread, seed)
+            seed!(
+                "add_granular",
+                (
+                    String, f32, f32, String, String, String, f32, f32, f32, f32, f32, f32,
+                    String, f32, Option<u64>
+                ),
+                granulars
+            );
+                // add_slicer(name, gain, angle, sample, floww, base_note, gains, reverse)
+            seed!(
+                "add_slicer",
+                (String, f32, f32, String, String, i32, Option<Vec<f32>>, Option<Vec<bool>>),
+                slicers
+            );
+                // add_clip(name, gain, angle, sample, start_sec, options)
+            seed!("add_clip", (String, f32, f32, String, f32, ClipOptions), clips);
                 // connect(name, name)
             seed!("connect", (String, String), edges);
             // ---- Output
@@ -165,6 +303,11 @@ impl State{
 
         self.cs = cs;
         self.bd = bd;
+        self.bpm = bpm;
+        // samples fitted to beats change with the tempo
+        for (_, _, options) in new_samples.iter_mut(){
+            if options.fit_to_beats.is_some() { options.bpm = bpm; }
+        }
         self.render_sr = render_sr;
         self.output_file = output_file;
         self.output_vertex = output_vertex;
@@ -205,19 +348,56 @@ impl State{
                     s = DEFAULT, b = BLUE, x = name);
             self.sb.mark_dead(&name);
         }
+        // instruments own samples in the sample bank
+        let mut new_instruments = new_sfzs.into_iter()
+            .map(|(name, file)| (name, file, String::from("sfz")))
+            .chain(new_sf2s.into_iter().map(|(name, file)| (name, file, String::from("sf2"))))
+            .collect::<Vec<_>>();
+        let (pos_instruments, neg) = diff(&self.cur_instruments, &new_instruments);
+        for (name, _, _) in neg {
+            println!("{s}Info: instrument {b}\"{x}\"{s} will be removed.",
+                    s = DEFAULT, b = BLUE, x = name);
+            self.ib.remove(&name, &mut self.sb);
+        }
         println!("{}Status: refreshing sample bank.", DEFAULT);
         self.sb.refresh();
         let mut to_exclude = Vec::new();
-        for (name, file, method) in pos {
+        for (name, _, _) in &pos {
             println!("{s}Status: adding sample {b}\"{x}\"{s} to the sample bank.",
                     s = DEFAULT, b = BLUE, x = name);
-            if let Err(msg) = self.sb.add(name.clone(), &file, SampleLoadMethod::from(&method)){
+        }
+        // decoding and resampling happens in parallel, adding to the bank in order of name
+        let sb = &self.sb;
+        let mut loaded = pos.into_par_iter()
+            .map(|(name, file, options)| {
+                let res = sb.prepare(&name, &file, &options);
+                (name, res)
+            })
+            .collect::<Vec<_>>();
+        loaded.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, res) in loaded {
+            if let Err(msg) = res.and_then(|sample| self.sb.add_prepared(name.clone(), sample)){
                 println!("{}{}", RED, msg);
                 to_exclude.push(name);
             }
         }
         do_excluding!(to_exclude, new_samples, self.cur_samples);
 
+        let mut to_exclude = Vec::new();
+        for (name, file, kind) in pos_instruments {
+            println!("{s}Status: adding instrument {b}\"{x}\"{s}.",
+                    s = DEFAULT, b = BLUE, x = name);
+            let res = if kind == "sf2" { self.ib.add_sf2(name.clone(), &file, &mut self.sb) }
+                else { self.ib.add_sfz(name.clone(), &file, &mut self.sb) };
+            if let Err(msg) = res{
+                println!("{}{}", RED, msg);
+                to_exclude.push(name);
+            }
+        }
+        // clean up after instruments that failed halfway
+        self.sb.refresh();
+        do_excluding!(to_exclude, new_instruments, self.cur_instruments);
+
         // Same for resources
         let (pos, neg) = diff(&self.cur_resources, &new_resources);
         for (name, _) in neg {
@@ -244,6 +424,12 @@ impl State{
                 return;
             }
         }
+        for (name, file) in midi_channels{
+            if let Err(msg) = self.fb.add_midi_channels(name, &file){
+                println!("{}{}", RED, msg);
+                return;
+            }
+        }
         for name in streams{
             println!("{}", name);
             self.fb.declare_stream(name);
@@ -347,10 +533,13 @@ impl State{
             );
         }
         for (name, gain, angle, sample) in &sampleloops {
-            let index = get_index!(self.sb, sample, name, "sample");
-            self.g.add(
-                Vertex::new(bl, *gain, *angle, 0.0, VertexExt::sample_loop(index)), name.to_owned()
-            );
+            // streamed samples are read from disk by the vertex itself
+            let ext = if let Some(source) = self.sb.get_stream(sample){
+                VertexExt::stream_loop(StreamReader::new(source, true))
+            } else {
+                VertexExt::sample_loop(get_index!(self.sb, sample, name, "sample"))
+            };
+            self.g.add(Vertex::new(bl, *gain, *angle, 0.0, ext), name.to_owned());
         }
         for (name, gain, angle, sample, floww, note) in &samplemultis {
             let sample = get_index!(self.sb, sample, name, "sample");
@@ -455,6 +644,368 @@ impl State{
                     VertexExt::band_pass(*cut_off_hz_low, *cut_off_hz_high, *pass, psr)),
                 name.to_owned());
         }
+        for (
+            name, gain, angle, wet, mode, cutoff_hz, resonance, floww, env_oct, conf_arr,
+            key_track, vel_amount
+        ) in &filters
+        {
+            let floww = match floww.as_deref(){
+                None | Some("") => None,
+                Some(floww) => Some(get_index!(self.fb, floww, name, "floww")),
+            };
+            let conf = if let Some(config) = build_adsr_conf(conf_arr.as_deref().unwrap_or(&[])){
+                config
+            } else {
+                panic!("ADSR config must have 6 or 9 elements");
+            };
+            self.g.add(
+                Vertex::new(bl, *gain, *angle, *wet, VertexExt::filter(
+                    SvfMode::from(mode), *cutoff_hz, *resonance, floww, env_oct.unwrap_or(0.0),
+                    key_track.unwrap_or(0.0), vel_amount.unwrap_or(0.0), conf
+                )),
+                name.to_owned()
+            );
+        }
+        for (name, gain, angle, wet, room_size, damping, pre_delay_ms, width) in &reverbs {
+            self.g.add(Vertex::new(bl, *gain, *angle, *wet,
+                    VertexExt::reverb(*room_size, *damping, *pre_delay_ms, *width, psr)),
+                name.to_owned());
+        }
+        for (name, gain, angle, wet, ir_sample) in &convolvers {
+            let index = get_index!(self.sb, ir_sample, name, "sample");
+            self.g.add(
+                Vertex::new(bl, *gain, *angle, *wet, VertexExt::convolver(index)), name.to_owned()
+            );
+        }
+        for (name, gain, angle, wet, time_l, time_r, feedback, ping_pong, damping_hz) in &delays {
+            let mut times = [0.0; 2];
+            for (time, string) in times.iter_mut().zip([time_l, time_r]){
+                *time = match parse_delay_time(string, self.bpm){
+                    Some(t) => t,
+                    None => {
+                        println!("{r}Could not parse delay time {b}\"{t}\"{r} for vertex {b}\"{n}\"{r}.",
+                            r = RED, b = BLUE, t = string, n = name);
+                        return;
+                    }
+                };
+            }
+            self.g.add(Vertex::new(bl, *gain, *angle, *wet,
+                    VertexExt::delay(times[0], times[1], *feedback, *ping_pong, *damping_hz, psr)),
+                name.to_owned());
+        }
+        for (name, gain, angle, wet, rate_hz, depth_ms, feedback, stereo_phase) in &choruses {
+            self.g.add(Vertex::new(bl, *gain, *angle, *wet,
+                    VertexExt::chorus(*rate_hz, *depth_ms, *feedback, *stereo_phase, psr)),
+                name.to_owned());
+        }
+        for (name, gain, angle, wet, rate_hz, depth_ms, feedback, stereo_phase) in &flangers {
+            self.g.add(Vertex::new(bl, *gain, *angle, *wet,
+                    VertexExt::flanger(*rate_hz, *depth_ms, *feedback, *stereo_phase, psr)),
+                name.to_owned());
+        }
+        for (name, gain, angle, wet, rate_hz, depth, feedback, stereo_phase, stages) in &phasers {
+            let stages = stages.unwrap_or(4).max(1) as usize;
+            self.g.add(Vertex::new(bl, *gain, *angle, *wet,
+                    VertexExt::phaser(*rate_hz, *depth, *feedback, *stereo_phase, stages, psr)),
+                name.to_owned());
+        }
+        for (
+            name, gain, angle, wet, mode, drive, bias, tone_hz, oversampling, bits, downsample
+        ) in &distortions
+        {
+            let oversampling = oversampling.unwrap_or(4).max(1) as usize;
+            let bits = bits.unwrap_or(8.0);
+            let downsample = downsample.unwrap_or(1).max(1) as usize;
+            self.g.add(Vertex::new(bl, *gain, *angle, *wet,
+                    VertexExt::distortion(
+                        DistortionMode::from(mode), *drive, *bias, *tone_hz, oversampling, bits,
+                        downsample, psr
+                    )),
+                name.to_owned());
+        }
+        for (name, gain, angle, color, floww, conf_arr, seed) in &noises {
+            let floww = match floww.as_deref(){
+                None | Some("") => None,
+                Some(floww) => Some(get_index!(self.fb, floww, name, "floww")),
+            };
+            let adsr = if let Some(config) = build_adsr_conf(conf_arr.as_deref().unwrap_or(&[])){
+                config
+            } else {
+                panic!("ADSR config must have 6 or 9 elements");
+            };
+            self.g.add(Vertex::new(bl, *gain, *angle, 0.0,
+                    VertexExt::noise(NoiseColor::from(color), seed.unwrap_or(0), floww, adsr)),
+                name.to_owned());
+        }
+        for (name, gain, angle, floww, op_arrs, algorithm, feedback, matrix) in &fms {
+            let floww = get_index!(self.fb, floww, name, "floww");
+            let mut ops = Vec::new();
+            for arr in op_arrs{
+                if let Some(op) = FmOperator::from_arr(arr){
+                    ops.push(op);
+                } else {
+                    panic!("FM operator must have a ratio, detune, level and 6 or 9 element ADSR config");
+                }
+            }
+            let conf = if *algorithm > 0 {
+                FmConf::algorithm(ops, *algorithm as usize, *feedback)
+            } else {
+                FmConf::from_matrix(ops, matrix.as_deref().unwrap_or(&[]))
+            };
+            let conf = if let Some(conf) = conf{ conf }
+            else {
+                println!(
+                    "{r}FM vertex {b}\"{n}\"{r} needs {min} to {max} operators and an algorithm from 1 to 6 or an n by n + 1 matrix.",
+                    r = RED, b = BLUE, n = name, min = MIN_OPERATORS, max = MAX_OPERATORS
+                );
+                return;
+            };
+            self.g.add(Vertex::new(bl, *gain, *angle, 0.0, VertexExt::fm(floww, conf)),
+                name.to_owned());
+        }
+        for (
+            name, gain, angle, floww, wave, pulse_width, pwm_rate, pwm_depth, sync_ratio, unison,
+            detune_cents, spread, conf_arr
+        ) in &vas
+        {
+            let floww = get_index!(self.fb, floww, name, "floww");
+            let adsr = if let Some(config) = build_adsr_conf(conf_arr){ config }
+            else { panic!("ADSR config must have 6 or 9 elements"); };
+            let conf = VaConf{
+                wave: VaWave::from(wave),
+                pulse_width: *pulse_width,
+                pwm_rate: *pwm_rate,
+                pwm_depth: *pwm_depth,
+                sync_ratio: *sync_ratio,
+                unison: (*unison).clamp(1, MAX_UNISON as i32) as usize,
+                detune_cents: *detune_cents,
+                spread: *spread,
+                adsr,
+            };
+            self.g.add(Vertex::new(bl, *gain, *angle, 0.0, VertexExt::va(floww, conf)),
+                name.to_owned());
+        }
+        for (
+            name, gain, angle, floww, decay_sec, damping, brightness, pick_position, spread,
+            release_sec, seed
+        ) in &plucks
+        {
+            let floww = get_index!(self.fb, floww, name, "floww");
+            let conf = PluckConf{
+                decay_sec: *decay_sec,
+                damping: *damping,
+                brightness: *brightness,
+                pick_position: *pick_position,
+                spread: *spread,
+                release_sec: *release_sec,
+            };
+            self.g.add(Vertex::new(bl, *gain, *angle, 0.0,
+                    VertexExt::pluck(floww, conf, seed.unwrap_or(0))),
+                name.to_owned());
+        }
+        let mut drums = Vec::new();
+        for (name, gain, angle, floww, note, pitch_start_hz, pitch_end_hz, pitch_decay_sec,
+            decay_sec, click) in &kicks
+        {
+            drums.push((name, gain, angle, floww, note, DrumConf::Kick{
+                pitch_start_hz: *pitch_start_hz,
+                pitch_end_hz: *pitch_end_hz,
+                pitch_decay_sec: *pitch_decay_sec,
+                decay_sec: *decay_sec,
+                click: *click,
+            }));
+        }
+        for (name, gain, angle, floww, note, tone_hz, tone_decay_sec, noise_decay_sec, noise_hz,
+            tone_mix) in &snares
+        {
+            drums.push((name, gain, angle, floww, note, DrumConf::Snare{
+                tone_hz: *tone_hz,
+                tone_decay_sec: *tone_decay_sec,
+                noise_decay_sec: *noise_decay_sec,
+                noise_hz: *noise_hz,
+                tone_mix: *tone_mix,
+            }));
+        }
+        for (name, gain, angle, floww, note, decay_sec, filter_hz, metallic) in &hats {
+            drums.push((name, gain, angle, floww, note, DrumConf::Hat{
+                decay_sec: *decay_sec,
+                filter_hz: *filter_hz,
+                metallic: *metallic,
+            }));
+        }
+        for (name, gain, angle, floww, note, conf) in drums {
+            let floww = get_index!(self.fb, floww, name, "floww");
+            let note = if note < &0 { None }
+            else { Some(*note as usize) };
+            self.g.add(Vertex::new(bl, *gain, *angle, 0.0, VertexExt::drum(floww, note, conf)),
+                name.to_owned());
+        }
+        for (
+            name, gain, angle, sample, floww, root_note, interpolation, conf_arr, loop_start,
+            loop_end
+        ) in &samplers
+        {
+            let sample = get_index!(self.sb, sample, name, "sample");
+            let floww = get_index!(self.fb, floww, name, "floww");
+            let adsr = match conf_arr{
+                None => None,
+                Some(arr) if arr.is_empty() => None,
+                Some(arr) => if let Some(config) = build_adsr_conf(arr){ Some(config) }
+                    else { panic!("ADSR config must have 6 or 9 elements"); },
+            };
+            // the loop points of the sample itself when none are given
+            let loop_sec = match (loop_start, loop_end){
+                (Some(start), Some(end)) if *start >= 0.0 && end > start => Some((*start, *end)),
+                _ => self.sb.get_loop(sample).map(|(start, end)| {
+                    let sr = self.sb.sample_rate() as f32;
+                    (start as f32 / sr, end as f32 / sr)
+                }),
+            };
+            let conf = SamplerConf{
+                root_note: *root_note,
+                interpolation: Interpolation::from(interpolation),
+                adsr,
+                loop_sec,
+            };
+            self.g.add(
+                Vertex::new(bl, *gain, *angle, 0.0, VertexExt::sampler(sample, floww, conf)),
+                name.to_owned()
+            );
+        }
+        macro_rules! get_instrument{
+            ($instrument:expr, $name:expr) => {
+                if let Some(instrument) = self.ib.get($instrument) { instrument }
+                else {
+                    println!("{}Could not get instrument for vertex {}\"{}\"{}.",
+                        RED, BLUE, $name, DEFAULT);
+                    return;
+                }
+            }
+        }
+        macro_rules! resolve_zones{
+            ($preset:expr, $name:expr) => {{
+                let mut zones = Vec::new();
+                for zone in &$preset.zones{
+                    let sample = get_index!(self.sb, &zone.sample, $name, "sample");
+                    zones.push((zone.clone(), sample));
+                }
+                zones
+            }}
+        }
+        for (name, gain, angle, instrument, floww) in &sfzs {
+            let floww = get_index!(self.fb, floww, name, "floww");
+            let instrument = get_instrument!(instrument, name);
+            let zones = resolve_zones!(instrument.presets[0], name);
+            self.g.add(
+                Vertex::new(bl, *gain, *angle, 0.0, VertexExt::instrument(floww, zones)),
+                name.to_owned()
+            );
+        }
+        for (name, gain, angle, bank, preset, floww) in &sf2s {
+            let floww = get_index!(self.fb, floww, name, "floww");
+            let instrument = get_instrument!(bank, name);
+            // "auto", "bank:program" or "program"
+            let (bank, program) = if preset == "auto"{
+                self.fb.get_program(floww).unwrap_or((0, 0))
+            } else {
+                let parsed = if let Some((b, p)) = preset.split_once(':'){
+                    b.trim().parse::<u16>().ok().zip(p.trim().parse::<u16>().ok())
+                } else {
+                    preset.trim().parse::<u16>().ok().map(|p| (0, p))
+                };
+                if let Some(x) = parsed { x }
+                else {
+                    println!("{r}Could not parse preset {b}\"{p}\"{r} for vertex {b}\"{n}\"{r}.",
+                        r = RED, b = BLUE, p = preset, n = name);
+                    return;
+                }
+            };
+            let preset = if let Some(preset) = instrument.preset(bank, program) { preset }
+            else {
+                println!(
+                    "{r}Could not find preset {b}{bank}:{p}{r} for vertex {b}\"{n}\"{r}.",
+                    r = RED, b = BLUE, bank = bank, p = program, n = name
+                );
+                return;
+            };
+            println!("{s}Info: vertex {b}\"{n}\"{s} plays preset {b}\"{p}\"{s}.",
+                s = DEFAULT, b = BLUE, n = name, p = preset.name);
+            let zones = resolve_zones!(preset, name);
+            self.g.add(
+                Vertex::new(bl, *gain, *angle, 0.0, VertexExt::instrument(floww, zones)),
+                name.to_owned()
+            );
+        }
+
+        for (
+            name, gain, angle, sample, floww, position_floww, grain_ms, density, position,
+            scan_rate, jitter_ms, pitch_spread_cents, window, stereo_spread, seed
+        ) in &granulars
+        {
+            let sample = get_index!(self.sb, sample, name, "sample");
+            let floww = if floww.is_empty() { None }
+                else { Some(get_index!(self.fb, floww, name, "floww")) };
+            let position_floww = if position_floww.is_empty() { None }
+                else { Some(get_index!(self.fb, position_floww, name, "floww")) };
+            let conf = GranularConf{
+                grain_sec: grain_ms * 0.001,
+                density: *density,
+                position: *position,
+                scan_rate: *scan_rate,
+                jitter_sec: jitter_ms * 0.001,
+                pitch_spread_cents: *pitch_spread_cents,
+                window: GrainWindow::from(window),
+                stereo_spread: *stereo_spread,
+                seed: seed.unwrap_or(0),
+            };
+            self.g.add(
+                Vertex::new(bl, *gain, *angle, 0.0,
+                    VertexExt::granular(sample, floww, position_floww, Granular::new(conf))),
+                name.to_owned()
+            );
+        }
+
+        for (name, gain, angle, sample, floww, base_note, gains, reverse) in &slicers{
+            let sample_index = get_index!(self.sb, sample, name, "sample");
+            let floww = get_index!(self.fb, floww, name, "floww");
+            // the last slice_sample of a sample counts
+            let slices = if let Some((_, n, threshold)) = slicings.iter().rev()
+                .find(|(s, _, _)| s == sample)
+            {
+                let s = self.sb.get_sample(sample_index);
+                match threshold{
+                    Some(db) => onset_slices(s, *db, *n, self.sb.sample_rate()),
+                    None => even_slices(s.len(), *n),
+                }
+            } else {
+                println!("{r}Sample {b}\"{s}\"{r} of vertex {b}\"{n}\"{r} is not sliced.",
+                    r = RED, b = BLUE, s = sample, n = name);
+                return;
+            };
+            println!("{s}Info: sliced {b}{x}{s} into {b}{c}{s} slices for vertex {b}{n}{s}.",
+                s = DEFAULT, b = BLUE, x = sample, c = slices.len(), n = name);
+            self.g.add(
+                Vertex::new(bl, *gain, *angle, 0.0, VertexExt::slicer(
+                    sample_index, floww, *base_note as f32, slices,
+                    gains.clone().unwrap_or_default(), reverse.clone().unwrap_or_default()
+                )),
+                name.to_owned()
+            );
+        }
+
+        for (name, gain, angle, sample, start_sec, options) in &clips{
+            let (source, sample_len) = if let Some(source) = self.sb.get_stream(sample){
+                (ClipSource::Stream(Box::new(StreamReader::new(source, false))), source.len)
+            } else {
+                let index = get_index!(self.sb, sample, name, "sample");
+                (ClipSource::Memory(index), self.sb.get_sample(index).len())
+            };
+            let conf = ClipConf::new(*start_sec, options, sample_len, psr);
+            self.g.add(
+                Vertex::new(bl, *gain, *angle, 0.0, VertexExt::clip(source, conf)),
+                name.to_owned()
+            );
+        }
 
         for (a, b) in &edges { self.g.connect(a, b); }
 
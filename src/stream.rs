@@ -0,0 +1,239 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::PathBuf,
+    sync::mpsc::{ channel, sync_channel, Receiver, Sender, SyncSender },
+    thread,
+};
+
+// frames per chunk and how many chunks are read ahead
+const CHUNK: usize = 8192;
+const READ_AHEAD: usize = 8;
+
+// A wav file at the project sample rate that is read from disk while playing. Channels are
+// picked and the gain applied while reading.
+#[derive(Clone)]
+pub struct StreamSource{
+    pub path: PathBuf,
+    // frames
+    pub len: usize,
+    // channels of the file that become left and right
+    pub picks: (usize, usize),
+    pub gain: f32,
+    pub loop_frames: Option<(usize, usize)>,
+}
+
+// an empty chunk means the stream ended
+struct Chunk{
+    generation: usize,
+    l: Vec<f32>,
+    r: Vec<f32>,
+}
+
+// Reads a StreamSource ahead on a background thread. Every vertex playing a stream has its own
+// reader. Seeking bumps the generation so the chunks that were read ahead are skipped.
+pub struct StreamReader{
+    control: Sender<(usize, usize)>,
+    data: Receiver<Chunk>,
+    generation: usize,
+    chunk: Chunk,
+    at: usize,
+    ended: bool,
+    // (start, end) of the loop
+    looping: Option<(usize, usize)>,
+}
+
+impl StreamReader{
+    // with looping it plays to the end of the loop (or file) and loops from there
+    pub fn new(source: &StreamSource, looping: bool) -> Self{
+        let (control, control_rx) = channel();
+        let (data_tx, data) = sync_channel(READ_AHEAD);
+        let source = source.clone();
+        let looping = if looping { Some(source.loop_frames.unwrap_or((0, source.len))) }
+            else { None };
+        thread::spawn(move || read_thread(source, looping, control_rx, data_tx));
+        Self{
+            looping,
+            control,
+            data,
+            generation: 0,
+            chunk: Chunk{ generation: 0, l: Vec::new(), r: Vec::new() },
+            at: 0,
+            ended: false,
+        }
+    }
+
+    // go to frame t of the playback, loops included
+    pub fn set_time(&mut self, t: usize){
+        let frame = match self.looping{
+            Some((start, end)) if t >= end && end > start => start + (t - start) % (end - start),
+            _ => t,
+        };
+        self.seek(frame);
+    }
+
+    fn seek(&mut self, frame: usize){
+        self.generation += 1;
+        self.chunk.l.clear();
+        self.chunk.r.clear();
+        self.at = 0;
+        self.ended = false;
+        let _ = self.control.send((self.generation, frame));
+    }
+
+    // waits for the disk when it is behind, so renders never miss audio
+    #[inline]
+    pub fn next(&mut self) -> (f32, f32){
+        while self.at >= self.chunk.l.len(){
+            if self.ended { return (0.0, 0.0); }
+            match self.data.recv(){
+                Ok(chunk) if chunk.generation != self.generation => continue,
+                Ok(chunk) => {
+                    self.ended = chunk.l.is_empty();
+                    self.chunk = chunk;
+                    self.at = 0;
+                },
+                Err(_) => self.ended = true,
+            }
+        }
+        let frame = (self.chunk.l[self.at], self.chunk.r[self.at]);
+        self.at += 1;
+        frame
+    }
+}
+
+type WavReader = hound::WavReader<BufReader<File>>;
+
+fn read_chunk(reader: &mut WavReader, source: &StreamSource, frames: usize)
+    -> (Vec<f32>, Vec<f32>)
+{
+    let spec = reader.spec();
+    let channels = spec.channels as usize;
+    let mut frame = vec![0.0; channels];
+    let mut l = Vec::with_capacity(frames);
+    let mut r = Vec::with_capacity(frames);
+    let mut push = |frame: &[f32]| {
+        l.push(frame[source.picks.0] * source.gain);
+        r.push(frame[source.picks.1] * source.gain);
+    };
+    if spec.sample_format == hound::SampleFormat::Float{
+        let mut samples = reader.samples::<f32>();
+        'frames: for _ in 0..frames{
+            for s in frame.iter_mut(){
+                if let Some(x) = samples.next() { *s = x.unwrap_or(0.0); } else { break 'frames; }
+            }
+            push(&frame);
+        }
+    } else {
+        let max = (1u64 << (spec.bits_per_sample.max(1) - 1)) as f32;
+        let mut samples = reader.samples::<i32>();
+        'frames: for _ in 0..frames{
+            for s in frame.iter_mut(){
+                if let Some(x) = samples.next() { *s = x.unwrap_or(0) as f32 / max; }
+                else { break 'frames; }
+            }
+            push(&frame);
+        }
+    }
+    (l, r)
+}
+
+fn read_thread(
+    source: StreamSource, looping: Option<(usize, usize)>, control: Receiver<(usize, usize)>,
+    data: SyncSender<Chunk>
+){
+    let ended = |generation| Chunk{ generation, l: Vec::new(), r: Vec::new() };
+    let mut reader = match hound::WavReader::open(&source.path){
+        Ok(reader) => reader,
+        Err(_) => {
+            let _ = data.send(ended(0));
+            return;
+        },
+    };
+    let stop = match looping{
+        Some((_, end)) => end.min(source.len),
+        None => source.len,
+    };
+    let loop_start = looping.map(|(start, _)| start).filter(|start| *start < stop);
+    let (mut generation, mut pos) = (0, 0);
+    let seek = |reader: &mut WavReader, pos: usize| {
+        let _ = reader.seek(pos.min(source.len) as u32);
+    };
+    loop{
+        // only the last seek matters
+        let mut target = None;
+        while let Ok(command) = control.try_recv() { target = Some(command); }
+        if let Some((g, frame)) = target{
+            generation = g;
+            pos = frame;
+            seek(&mut reader, pos);
+        }
+        if pos >= stop{
+            if let Some(start) = loop_start{
+                pos = start;
+                seek(&mut reader, pos);
+                continue;
+            }
+            if data.send(ended(generation)).is_err() { return; }
+            // nothing to read until the next seek
+            match control.recv(){
+                Ok((g, frame)) => {
+                    generation = g;
+                    pos = frame;
+                    seek(&mut reader, pos);
+                    continue;
+                },
+                Err(_) => return,
+            }
+        }
+        let (l, r) = read_chunk(&mut reader, &source, CHUNK.min(stop - pos));
+        if l.is_empty(){
+            // the file is shorter than it said
+            pos = stop;
+            continue;
+        }
+        pos += l.len();
+        if data.send(Chunk{ generation, l, r }).is_err() { return; }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::stream::*;
+
+    #[test]
+    fn read_seek_and_loop(){
+        let path = std::env::temp_dir().join("termdaw_stream_test.wav");
+        let spec = hound::WavSpec{
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        let len = CHUNK * 2 + 100;
+        for i in 0..len{
+            writer.write_sample((i % 1000) as i16).unwrap();
+            writer.write_sample(-((i % 1000) as i16)).unwrap();
+        }
+        writer.finalize().unwrap();
+        let frame = |i: usize| ((i % 1000) as f32 / 32768.0, -((i % 1000) as f32) / 32768.0);
+        let source = StreamSource{
+            path: path.clone(), len, picks: (0, 1), gain: 1.0, loop_frames: Some((10, 20)),
+        };
+        let mut reader = StreamReader::new(&source, false);
+        assert_eq!(reader.next(), frame(0));
+        reader.set_time(CHUNK + 5);
+        assert_eq!(reader.next(), frame(CHUNK + 5));
+        reader.set_time(len - 1);
+        assert_eq!(reader.next(), frame(len - 1));
+        assert_eq!(reader.next(), (0.0, 0.0));
+        let mut looping = StreamReader::new(&source, true);
+        let played = (0..25).map(|_| looping.next()).collect::<Vec<_>>();
+        assert_eq!(played[19], frame(19));
+        assert_eq!(played[20], frame(10));
+        looping.set_time(33);
+        assert_eq!(looping.next(), frame(13));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use std::f32::consts::PI;
+
+use realfft::{ RealFftPlanner, num_complex::Complex };
+
+// frame size and the hop between output frames of the phase vocoder
+const FRAME: usize = 2048;
+const HOP: usize = FRAME / 4;
+// the output is rendered this much early and cut off, so its start is covered by as many
+// windows as the rest
+const PAD: usize = FRAME - HOP;
+
+fn wrap(phase: f32) -> f32{
+    phase - 2.0 * PI * (phase / (2.0 * PI)).round()
+}
+
+// Phase vocoder: changes the length of a stereo signal by the stretch factor without changing
+// the pitch. Transients smear a bit, like with every phase vocoder.
+// The phases come from the mid signal and both channels are rotated by the same amount, so the
+// phase difference between left and right and with it the stereo image stays as it was.
+pub fn time_stretch(l: &[f32], r: &[f32], stretch: f32) -> (Vec<f32>, Vec<f32>){
+    let len = l.len().min(r.len());
+    if (stretch - 1.0).abs() < 0.0001 || len == 0 { return (l.to_vec(), r.to_vec()); }
+    let out_len = (len as f64 * stretch as f64).round() as usize;
+    let window = (0..FRAME)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FRAME as f32).cos())
+        .collect::<Vec<_>>();
+    let mut planner = RealFftPlanner::<f32>::new();
+    let fft = planner.plan_fft_forward(FRAME);
+    let ifft = planner.plan_fft_inverse(FRAME);
+    let bins = FRAME / 2 + 1;
+    let mut time = vec![0.0; FRAME];
+    let mut spectra = [vec![Complex::new(0.0, 0.0); bins], vec![Complex::new(0.0, 0.0); bins]];
+    let mut last_phase = vec![0.0f32; bins];
+    let mut acc_phase = vec![0.0f32; bins];
+    let mut outs = [vec![0.0; out_len + PAD + FRAME], vec![0.0; out_len + PAD + FRAME]];
+    let mut last_start = 0;
+    for frame in 0..(out_len + PAD) / HOP + 1{
+        // the input frame is centred where the centre of the output frame maps to
+        let centre = (frame * HOP) as f64 - PAD as f64 + (FRAME / 2) as f64;
+        let start = (centre / stretch as f64).round() as i64 - (FRAME / 2) as i64;
+        for (x, spectrum) in [l, r].iter().zip(spectra.iter_mut()){
+            for (i, t) in time.iter_mut().enumerate(){
+                let at = start + i as i64;
+                let s = if at < 0 { 0.0 } else { x.get(at as usize).copied().unwrap_or(0.0) };
+                *t = s * window[i];
+            }
+            fft.process(&mut time, spectrum).expect("time_stretch: fft size mismatch");
+        }
+        let hop_in = (start - last_start) as f32;
+        let mid = spectra[0].iter().zip(&spectra[1]).map(|(a, b)| (a + b).to_polar())
+            .collect::<Vec<_>>();
+        // phase locking: only the peaks get their phase from their frequency, the bins around
+        // them keep their phase relative to the peak, so partials don't smear over many bins
+        let peaks = (0..bins).filter(|k| {
+            let m = mid[*k].0;
+            (*k == 0 || m > mid[k - 1].0) && (k + 1 == bins || m >= mid[k + 1].0)
+        }).collect::<Vec<_>>();
+        for p in &peaks{
+            let (k, phase) = (*p, mid[*p].1);
+            let omega = 2.0 * PI * k as f32 / FRAME as f32;
+            acc_phase[k] = if frame == 0 { phase }
+                else {
+                    // the real frequency of the peak from how far its phase moved
+                    let freq = if hop_in > 0.0 {
+                        omega + wrap(phase - last_phase[k] - omega * hop_in) / hop_in
+                    } else { omega };
+                    wrap(acc_phase[k] + freq * HOP as f32)
+                };
+        }
+        let mut peak = 0;
+        for k in 0..bins{
+            // the closest peak
+            while peak + 1 < peaks.len() && peaks[peak + 1].abs_diff(k) < peaks[peak].abs_diff(k){
+                peak += 1;
+            }
+            if let Some(&p) = peaks.get(peak){
+                if p != k { acc_phase[k] = wrap(acc_phase[p] + mid[k].1 - mid[p].1); }
+            }
+            last_phase[k] = mid[k].1;
+            let rotation = Complex::from_polar(1.0, acc_phase[k] - mid[k].1);
+            spectra.iter_mut().for_each(|spectrum| spectrum[k] *= rotation);
+        }
+        last_start = start;
+        for (spectrum, out) in spectra.iter_mut().zip(outs.iter_mut()){
+            spectrum[0].im = 0.0;
+            spectrum[bins - 1].im = 0.0;
+            ifft.process(spectrum, &mut time).expect("time_stretch: fft size mismatch");
+            let at = frame * HOP;
+            for i in 0..FRAME{
+                out[at + i] += time[i] * window[i];
+            }
+        }
+    }
+    // the inverse fft is not normalized and the squared windows add up to 1.5 at this hop
+    let scale = 1.0 / (FRAME as f32 * 1.5);
+    let [l, r] = outs.map(|out| out[PAD..PAD + out_len].iter().map(|s| s * scale).collect());
+    (l, r)
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::stretch::*;
+
+    // zero crossings per frame, a rough frequency
+    fn crossings(x: &[f32]) -> f32{
+        let n = x.windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count();
+        n as f32 / x.len() as f32
+    }
+
+    fn peak(x: &[f32]) -> f32{
+        x.iter().fold(0.0f32, |m, s| m.max(s.abs()))
+    }
+
+    #[test]
+    fn keeps_pitch_and_level(){
+        let x = (0..44100).map(|i| (2.0 * PI * 440.0 * i as f32 / 44100.0).sin() * 0.5)
+            .collect::<Vec<_>>();
+        let half = x.iter().map(|s| s * 0.5).collect::<Vec<_>>();
+        for stretch in [0.5, 1.5]{
+            let (l, r) = time_stretch(&x, &half, stretch);
+            assert_eq!(l.len(), (x.len() as f32 * stretch).round() as usize);
+            // away from the edges
+            let mid = &l[FRAME * 2..l.len() - FRAME * 2];
+            assert!((crossings(mid) - crossings(&x)).abs() < 0.001);
+            assert!((peak(mid) - 0.5).abs() < 0.05, "stretch {} peak {}", stretch, peak(mid));
+            // the start keeps its level, so a hit on the first frame is not faded in
+            assert!((peak(&l[..HOP]) - 0.5).abs() < 0.05, "stretch {} start {}", stretch,
+                peak(&l[..HOP]));
+            // both channels get the same phases, the image stays
+            assert!(l.iter().zip(&r).all(|(l, r)| (l * 0.5 - r).abs() < 0.0001));
+        }
+    }
+}
@@ -35,3 +35,210 @@ pub fn triangle_sample(t: f32, hz: f32) -> f32{
 
 // formula's i made for the square_sine and topflat_sine oscilators
 // https://graphtoy.com/?f1(x,t)=min(sin(x),0)*2+1&v1=false&f2(x,t)=max(sin(x),0)*2-1&v2=false&f3(x,t)=0.4&v3=false&f4(x,t)=(min(sin(x),f3(0))+((1-f3(0))/2))*(2/(1+f3(0)))&v4=true&f5(x,t)=(max(sin(x),-f3(0))-((1-f3(0))/2))*(2/(1+f3(0)))&v5=false&f6(x,t)=clamp(sin(x),-f3(0),f3(0))*(1/f3(0))&v6=true&grid=true&coords=0,0,4.205926793776712
+
+pub const MAX_UNISON: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum VaWave{ Saw, Pulse }
+
+impl VaWave{
+    pub fn from(string: &str) -> Self{
+        match string{
+            "pulse" | "square" => VaWave::Pulse,
+            _ => VaWave::Saw,
+        }
+    }
+}
+
+pub struct VaConf{
+    pub wave: VaWave,
+    pub pulse_width: f32,
+    pub pwm_rate: f32,
+    pub pwm_depth: f32,
+    pub sync_ratio: f32,
+    pub unison: usize,
+    pub detune_cents: f32,
+    pub spread: f32,
+    pub adsr: AdsrConf,
+}
+
+// Phases of one note: every unison voice has a phase and the phase of its sync master
+#[derive(Clone, Copy)]
+pub struct VaVoice{
+    phases: [f32; MAX_UNISON],
+    masters: [f32; MAX_UNISON],
+    // synced voices play one frame late, so a reset can smooth the frame before it as well
+    delayed: [f32; MAX_UNISON],
+    lfo: f32,
+}
+
+impl VaVoice{
+    pub fn new(unison: usize) -> Self{
+        let mut phases = [0.0; MAX_UNISON];
+        // start the unison voices spread out so they don't flam on the attack
+        for (u, phase) in phases.iter_mut().enumerate().take(unison){
+            *phase = (u as f32 * 0.618034).fract();
+        }
+        Self{
+            phases,
+            masters: phases,
+            delayed: [0.0; MAX_UNISON],
+            lfo: 0.0,
+        }
+    }
+}
+
+// polynomial band limited step, t is the phase in cycles and dt the phase increment
+#[inline]
+pub fn poly_blep(t: f32, dt: f32) -> f32{
+    if t < dt{
+        let t = t / dt;
+        t + t - t * t - 1.0
+    } else if t > 1.0 - dt{
+        let t = (t - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+#[inline]
+fn va_naive(wave: VaWave, phase: f32, pw: f32) -> f32{
+    match wave{
+        VaWave::Saw => 2.0 * phase - 1.0,
+        VaWave::Pulse => if phase < pw { 1.0 } else { -1.0 },
+    }
+}
+
+#[inline]
+fn va_blep(wave: VaWave, phase: f32, dt: f32, pw: f32) -> f32{
+    match wave{
+        VaWave::Saw => 2.0 * phase - 1.0 - poly_blep(phase, dt),
+        VaWave::Pulse => {
+            let naive = if phase < pw { 1.0 } else { -1.0 };
+            naive + poly_blep(phase, dt) - poly_blep((phase - pw + 1.0).fract(), dt)
+        },
+    }
+}
+
+impl VaConf{
+    // returns (l, r) of one note, advances the phases
+    #[inline]
+    pub fn sample(&self, voice: &mut VaVoice, hz: f32, sr: usize) -> (f32, f32){
+        let sr = sr as f32;
+        let pw = (self.pulse_width + self.pwm_depth * (voice.lfo * 2.0 * PI).sin())
+            .clamp(0.02, 0.98);
+        voice.lfo = (voice.lfo + self.pwm_rate / sr).fract();
+        let unison = self.unison.clamp(1, MAX_UNISON);
+        let (mut l, mut r) = (0.0, 0.0);
+        for u in 0..unison{
+            // -1 for the lowest voice to 1 for the highest
+            let pos = if unison == 1 { 0.0 } else { u as f32 / (unison - 1) as f32 * 2.0 - 1.0 };
+            let hz = hz * 2.0f32.powf(pos * self.detune_cents / 1200.0);
+            let master_dt = (hz / sr).min(0.5);
+            let s = if self.sync_ratio > 1.0{
+                let dt = (master_dt * self.sync_ratio).min(0.5);
+                voice.masters[u] += master_dt;
+                let now = if voice.masters[u] >= 1.0{
+                    // hard sync: the slave resets where the master wrapped within this frame,
+                    // frac frames ago. The jump is smoothed by a blep on both sides of it.
+                    voice.masters[u] -= 1.0;
+                    let frac = (voice.masters[u] / master_dt).min(1.0);
+                    let at_reset = (voice.phases[u] + dt * (1.0 - frac)).fract();
+                    let jump = va_naive(self.wave, 0.0, pw) - va_naive(self.wave, at_reset, pw);
+                    voice.phases[u] = frac * dt;
+                    voice.delayed[u] += jump * 0.5 * frac * frac;
+                    va_naive(self.wave, voice.phases[u], pw)
+                        - jump * 0.5 * (1.0 - frac) * (1.0 - frac)
+                } else {
+                    voice.phases[u] = (voice.phases[u] + dt).fract();
+                    va_blep(self.wave, voice.phases[u], dt, pw)
+                };
+                std::mem::replace(&mut voice.delayed[u], now)
+            } else {
+                voice.phases[u] = (voice.phases[u] + master_dt).fract();
+                va_blep(self.wave, voice.phases[u], master_dt, pw)
+            };
+            // equal power panning, the middle is at unity
+            let angle = (pos * self.spread.clamp(0.0, 1.0) + 1.0) * PI * 0.25;
+            l += s * angle.cos() * std::f32::consts::SQRT_2;
+            r += s * angle.sin() * std::f32::consts::SQRT_2;
+        }
+        let norm = 1.0 / (unison as f32).sqrt();
+        (l * norm, r * norm)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::synth::*;
+
+    fn saw(sync_ratio: f32) -> VaConf{
+        VaConf{
+            wave: VaWave::Saw,
+            pulse_width: 0.5,
+            pwm_rate: 0.0,
+            pwm_depth: 0.0,
+            sync_ratio,
+            unison: 1,
+            detune_cents: 0.0,
+            spread: 0.0,
+            adsr: AdsrConf::default(),
+        }
+    }
+
+    // share of the energy that is not at a multiple of hz: aliasing
+    fn aliased(x: &[f32], hz: usize, sr: usize) -> f32{
+        let (mut harmonic, mut other) = (0.0, 0.0);
+        // x is one second long, so every bin of 100hz holds either harmonics or aliases
+        for bin in (100..sr / 2).step_by(100){
+            let (mut re, mut im) = (0.0f64, 0.0f64);
+            for (i, s) in x.iter().enumerate(){
+                let w = 2.0 * std::f64::consts::PI * bin as f64 * i as f64 / sr as f64;
+                re += *s as f64 * w.cos();
+                im += *s as f64 * w.sin();
+            }
+            let e = re * re + im * im;
+            if bin % hz == 0 { harmonic += e; } else { other += e; }
+        }
+        (other / (harmonic + other)) as f32
+    }
+
+    #[test]
+    fn saw_matches_poly_blep(){
+        let (hz, sr) = (440.0, 44100);
+        let conf = saw(1.0);
+        let mut voice = VaVoice::new(1);
+        let dt = hz / sr as f32;
+        let mut phase = 0.0f32;
+        for _ in 0..1000{
+            phase = (phase + dt).fract();
+            let (l, r) = conf.sample(&mut voice, hz, sr);
+            let expected = 2.0 * phase - 1.0 - poly_blep(phase, dt);
+            assert!((l - expected).abs() < 0.0001 && (r - expected).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn sync_is_band_limited(){
+        let (hz, sr) = (1000, 44100);
+        let conf = saw(2.5);
+        let mut voice = VaVoice::new(1);
+        let synced = (0..sr).map(|_| conf.sample(&mut voice, hz as f32, sr).0)
+            .collect::<Vec<_>>();
+        // the same sync without any smoothing
+        let (dt_master, dt) = (hz as f32 / sr as f32, hz as f32 * 2.5 / sr as f32);
+        let (mut master, mut slave) = (0.0f32, 0.0f32);
+        let naive = (0..sr).map(|_| {
+            master += dt_master;
+            slave = (slave + dt).fract();
+            if master >= 1.0{
+                master -= 1.0;
+                slave = master / dt_master * dt;
+            }
+            2.0 * slave - 1.0
+        }).collect::<Vec<_>>();
+        let (synced, naive) = (aliased(&synced, hz, sr), aliased(&naive, hz, sr));
+        assert!(synced < naive * 0.05, "{} {}", synced, naive);
+    }
+}